@@ -1,4 +1,4 @@
-SECTION "main",ROM0
+SECTION "main",ROM0[$100]
   ld a,10
   ld b,5
   add a,b
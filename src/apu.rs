@@ -0,0 +1,205 @@
+// The sound registers at 0xFF10-0xFF3F. No sound is generated yet, but the
+// registers read back like the hardware's, NR52 turns the whole thing on and
+// off, and the length counters turn channels off when they run out. Sweep
+// and envelopes don't run, so nothing else turns a channel off.
+//
+// Bits that can't be read back are 1, per register from NR10 to NR51.
+const READ_MASKS: [u8; 0x16] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, // NR50, NR51
+];
+
+// The frame sequencer steps at 512Hz, and clocks the length counters on
+// every other step.
+const FRAME_SEQUENCER_CYCLES: u32 = 8192;
+
+pub struct APU {
+    // NR10 to NR51
+    registers: [u8; 0x16],
+    wave_ram: [u8; 0x10],
+    // NR52 bit 7. The boot ROM leaves sound on.
+    enabled: bool,
+    // Channels that have been triggered with their DAC on, as NR52's low bits.
+    channels: u8,
+    // Per channel. Counts down to 0 while enabled by bit 6 of NRx4.
+    lengths: [u16; 4],
+    sequencer_cycles: u32,
+    sequencer_step: u8,
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            registers: [0; 0x16],
+            wave_ram: [0; 0x10],
+            enabled: true,
+            channels: 0,
+            lengths: [0; 4],
+            sequencer_cycles: 0,
+            sequencer_step: 0,
+        }
+    }
+
+    // Advances the frame sequencer by however many cycles of real time have
+    // passed, which doesn't speed up in double speed mode.
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+        self.sequencer_cycles += cycles;
+        while self.sequencer_cycles >= FRAME_SEQUENCER_CYCLES {
+            self.sequencer_cycles -= FRAME_SEQUENCER_CYCLES;
+            if self.sequencer_step % 2 == 0 {
+                self.clock_lengths();
+            }
+            self.sequencer_step = (self.sequencer_step + 1) % 8;
+        }
+    }
+
+    fn clock_lengths(&mut self) {
+        for channel in 0..4 {
+            let nrx4 = self.registers[channel * 5 + 4];
+            if nrx4 & 0x40 != 0 && self.lengths[channel] > 0 {
+                self.lengths[channel] -= 1;
+                if self.lengths[channel] == 0 {
+                    self.channels &= !(1 << channel);
+                }
+            }
+        }
+    }
+
+    pub fn fetch(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF10..=0xFF25 => {
+                let index = (addr - 0xFF10) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            0xFF26 => 0x70 | (self.enabled as u8) << 7 | self.channels,
+            0xFF30..=0xFF3F => self.wave_ram[(addr - 0xFF30) as usize],
+            // 0xFF27-0xFF2F aren't connected.
+            _ => 0xFF,
+        }
+    }
+
+    pub fn set(&mut self, addr: u16, val: u8) {
+        match addr {
+            // Everything but NR52 and wave RAM ignores writes while off.
+            0xFF10..=0xFF25 if self.enabled => {
+                self.registers[(addr - 0xFF10) as usize] = val;
+                self.update_channels(addr, val);
+            }
+            0xFF26 => {
+                self.enabled = val & 0x80 != 0;
+                // Turning sound off clears all of the registers.
+                if !self.enabled {
+                    self.registers = [0; 0x16];
+                    self.channels = 0;
+                    self.sequencer_cycles = 0;
+                    self.sequencer_step = 0;
+                }
+            }
+            0xFF30..=0xFF3F => self.wave_ram[(addr - 0xFF30) as usize] = val,
+            _ => (),
+        }
+    }
+
+    // Triggering a channel through bit 7 of NRx4 turns it on, as long as its
+    // DAC is on. Turning the DAC off turns the channel off. Writing NRx1 sets
+    // the length, and triggering with it run out starts it again from the
+    // longest.
+    fn update_channels(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF11 => self.lengths[0] = 64 - (val & 0x3F) as u16,
+            0xFF16 => self.lengths[1] = 64 - (val & 0x3F) as u16,
+            0xFF1B => self.lengths[2] = 256 - val as u16,
+            0xFF20 => self.lengths[3] = 64 - (val & 0x3F) as u16,
+            _ => (),
+        }
+        let (channel, dac_addr) = match addr {
+            0xFF12 | 0xFF14 => (0x01, 0xFF12),
+            0xFF17 | 0xFF19 => (0x02, 0xFF17),
+            0xFF1A | 0xFF1E => (0x04, 0xFF1A),
+            0xFF21 | 0xFF23 => (0x08, 0xFF21),
+            _ => return,
+        };
+        let dac = self.registers[(dac_addr - 0xFF10) as usize];
+        let dac_on = if dac_addr == 0xFF1A {
+            dac & 0x80 != 0
+        } else {
+            dac & 0xF8 != 0
+        };
+        if !dac_on {
+            self.channels &= !channel;
+        } else if addr != dac_addr && val & 0x80 != 0 {
+            self.channels |= channel;
+            let index = channel.trailing_zeros() as usize;
+            if self.lengths[index] == 0 {
+                self.lengths[index] = if index == 2 { 256 } else { 64 };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_bits_read_as_one() {
+        let mut apu = APU::new();
+        apu.set(0xFF11, 0x00);
+        assert_eq!(apu.fetch(0xFF11), 0x3F);
+        apu.set(0xFF13, 0x12);
+        assert_eq!(apu.fetch(0xFF13), 0xFF);
+        assert_eq!(apu.fetch(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn trigger_needs_the_dac_on() {
+        let mut apu = APU::new();
+        apu.set(0xFF14, 0x80);
+        assert_eq!(apu.fetch(0xFF26), 0xF0);
+        apu.set(0xFF12, 0xF0);
+        apu.set(0xFF14, 0x80);
+        assert_eq!(apu.fetch(0xFF26), 0xF1);
+        apu.set(0xFF12, 0x00);
+        assert_eq!(apu.fetch(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn length_counter_turns_the_channel_off() {
+        let mut apu = APU::new();
+        apu.set(0xFF12, 0xF0);
+        // A length of 2, enabled.
+        apu.set(0xFF11, 0x3E);
+        apu.set(0xFF14, 0xC0);
+        assert_eq!(apu.fetch(0xFF26), 0xF1);
+        apu.tick(FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.fetch(0xFF26), 0xF1);
+        // Only every other step clocks the lengths.
+        apu.tick(2 * FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.fetch(0xFF26), 0xF0);
+
+        // Without bit 6 the length doesn't count.
+        apu.set(0xFF11, 0x3F);
+        apu.set(0xFF14, 0x80);
+        apu.tick(8 * FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.fetch(0xFF26), 0xF1);
+    }
+
+    #[test]
+    fn turning_sound_off_clears_registers() {
+        let mut apu = APU::new();
+        apu.set(0xFF24, 0x77);
+        apu.set(0xFF30, 0x42);
+        apu.set(0xFF26, 0x00);
+        assert_eq!(apu.fetch(0xFF24), 0x00);
+        assert_eq!(apu.fetch(0xFF26), 0x70);
+        apu.set(0xFF24, 0x77);
+        assert_eq!(apu.fetch(0xFF24), 0x00);
+        assert_eq!(apu.fetch(0xFF30), 0x42);
+    }
+}
@@ -0,0 +1,132 @@
+// Pictures for the Pocket Camera's sensor to take, from a PNG file or
+// generated on the spot.
+extern crate png;
+
+use std::fs;
+use std::path::Path;
+
+use error::EmulatorError;
+
+// What the sensor captures, which is what ends up in a photo.
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+pub trait ImageSource {
+    // CAMERA_WIDTH by CAMERA_HEIGHT brightnesses, row by row, from 0 (black)
+    // to 255 (white).
+    fn capture(&self) -> Vec<u8>;
+}
+
+// A left to right gradient over the top half and a checkerboard over the
+// bottom half, which is enough to see every step of the camera's processing
+// without a picture to hand.
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                image.push(if y < CAMERA_HEIGHT / 2 {
+                    (x * 255 / (CAMERA_WIDTH - 1)) as u8
+                } else if (x / 8 + y / 8) % 2 == 0 {
+                    0xFF
+                } else {
+                    0x00
+                });
+            }
+        }
+        image
+    }
+}
+
+// A still picture, turned grey and stretched to the sensor's size.
+pub struct PngImage {
+    image: Vec<u8>,
+}
+
+impl PngImage {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<PngImage, EmulatorError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|err| EmulatorError::ImageLoadError(format!("{}: {}", path.display(), err)))?;
+        PngImage::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<PngImage, EmulatorError> {
+        let image_error = |err: png::DecodingError| EmulatorError::ImageLoadError(err.to_string());
+        let mut decoder = png::Decoder::new(bytes);
+        // Palettes and bit depths other than 8 come out as plain 8 bit
+        // samples.
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(image_error)?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(image_error)?;
+        let samples = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+
+        let mut image = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+        for y in 0..CAMERA_HEIGHT {
+            let row = y * height / CAMERA_HEIGHT * info.line_size;
+            for x in 0..CAMERA_WIDTH {
+                let pixel = &pixels[row + x * width / CAMERA_WIDTH * samples..][..samples];
+                image.push(match samples {
+                    // Grey, maybe with alpha, which is ignored.
+                    1 | 2 => pixel[0],
+                    _ => {
+                        let (r, g, b) = (pixel[0] as u32, pixel[1] as u32, pixel[2] as u32);
+                        ((r * 299 + g * 587 + b * 114) / 1000) as u8
+                    }
+                });
+            }
+        }
+        Ok(PngImage { image })
+    }
+}
+
+impl ImageSource for PngImage {
+    fn capture(&self) -> Vec<u8> {
+        self.image.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_fills_the_sensor() {
+        let image = TestPattern.capture();
+        assert_eq!(image.len(), CAMERA_WIDTH * CAMERA_HEIGHT);
+        assert_eq!(image[0], 0x00);
+        assert_eq!(image[CAMERA_WIDTH - 1], 0xFF);
+        assert_eq!(image[CAMERA_WIDTH * 64], 0xFF);
+        assert_eq!(image[CAMERA_WIDTH * 64 + 8], 0x00);
+    }
+
+    #[test]
+    fn pngs_are_greyed_and_stretched() {
+        // 2 by 1, red then white.
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF])
+                .unwrap();
+        }
+        let image = PngImage::from_bytes(&bytes).unwrap().capture();
+        assert_eq!(image.len(), CAMERA_WIDTH * CAMERA_HEIGHT);
+        assert_eq!(image[0], 76);
+        assert_eq!(image[CAMERA_WIDTH / 2 - 1], 76);
+        assert_eq!(image[CAMERA_WIDTH / 2], 0xFF);
+        assert_eq!(image[CAMERA_WIDTH * CAMERA_HEIGHT - 1], 0xFF);
+
+        assert!(matches!(
+            PngImage::from_bytes(b"not a png"),
+            Err(EmulatorError::ImageLoadError(_))
+        ));
+    }
+}
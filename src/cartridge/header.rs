@@ -0,0 +1,343 @@
+// The cartridge header at 0x100-0x14F, which says what hardware is on the
+// cartridge and lets the boot ROM check the dump isn't corrupt.
+use std::fmt;
+
+use error::EmulatorError;
+
+// The boot ROM refuses to start unless this is at 0x104-0x133.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// The chip that banks the ROM and RAM, or whatever else is in its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    TAMA5,
+    HuC3,
+    HuC1,
+    Unknown(u8),
+}
+
+impl fmt::Display for Mapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mapper::RomOnly => write!(f, "ROM only"),
+            Mapper::PocketCamera => write!(f, "Pocket Camera"),
+            Mapper::Unknown(code) => write!(f, "unknown (${:02x})", code),
+            mapper => write!(f, "{:?}", mapper),
+        }
+    }
+}
+
+// The cartridge type byte at 0x147, split into the mapper and whatever else
+// is on the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub rtc: bool,
+    pub rumble: bool,
+    // MBC7's accelerometer
+    pub sensor: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> CartridgeType {
+        let (mapper, ram, battery, rtc, rumble, sensor) = match code {
+            0x00 => (Mapper::RomOnly, false, false, false, false, false),
+            0x01 => (Mapper::MBC1, false, false, false, false, false),
+            0x02 => (Mapper::MBC1, true, false, false, false, false),
+            0x03 => (Mapper::MBC1, true, true, false, false, false),
+            0x05 => (Mapper::MBC2, false, false, false, false, false),
+            0x06 => (Mapper::MBC2, false, true, false, false, false),
+            0x08 => (Mapper::RomOnly, true, false, false, false, false),
+            0x09 => (Mapper::RomOnly, true, true, false, false, false),
+            0x0B => (Mapper::MMM01, false, false, false, false, false),
+            0x0C => (Mapper::MMM01, true, false, false, false, false),
+            0x0D => (Mapper::MMM01, true, true, false, false, false),
+            0x0F => (Mapper::MBC3, false, true, true, false, false),
+            0x10 => (Mapper::MBC3, true, true, true, false, false),
+            0x11 => (Mapper::MBC3, false, false, false, false, false),
+            0x12 => (Mapper::MBC3, true, false, false, false, false),
+            0x13 => (Mapper::MBC3, true, true, false, false, false),
+            0x19 => (Mapper::MBC5, false, false, false, false, false),
+            0x1A => (Mapper::MBC5, true, false, false, false, false),
+            0x1B => (Mapper::MBC5, true, true, false, false, false),
+            0x1C => (Mapper::MBC5, false, false, false, true, false),
+            0x1D => (Mapper::MBC5, true, false, false, true, false),
+            0x1E => (Mapper::MBC5, true, true, false, true, false),
+            0x20 => (Mapper::MBC6, true, true, false, false, false),
+            0x22 => (Mapper::MBC7, true, true, false, true, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false, false),
+            0xFD => (Mapper::TAMA5, true, true, true, false, false),
+            0xFE => (Mapper::HuC3, true, true, true, false, false),
+            0xFF => (Mapper::HuC1, true, true, false, false, false),
+            _ => (Mapper::Unknown(code), false, false, false, false, false),
+        };
+        CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            rtc,
+            rumble,
+            sensor,
+        }
+    }
+}
+
+// The CGB flag at 0x143, which on older cartridges is the last letter of the
+// title.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    // Works on both, with extra colour on the CGB
+    Enhanced,
+    Only,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // Four letters at 0x13F, only on some CGB cartridges.
+    pub manufacturer: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub old_licensee: u8,
+    // Two letters at 0x144, used when the old licensee is 0x33.
+    pub new_licensee: Option<String>,
+    pub cartridge_type: CartridgeType,
+    // The size codes, and the sizes they mean if they're known.
+    pub rom_size_code: u8,
+    pub rom_size: Option<usize>,
+    pub ram_size_code: u8,
+    pub ram_size: Option<usize>,
+    pub destination: Destination,
+    pub version: u8,
+    // Each checksum as stored in the header and as worked out from the ROM.
+    pub header_checksum: u8,
+    pub computed_header_checksum: u8,
+    pub global_checksum: u16,
+    pub computed_global_checksum: u16,
+    pub logo_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, EmulatorError> {
+        if rom.len() < 0x150 {
+            return Err(EmulatorError::TruncatedRom {
+                size: rom.len(),
+                header_size: None,
+            });
+        }
+
+        let cgb_support = match rom[0x143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // Newer cartridges shortened the title to make room for the CGB flag
+        // and sometimes a manufacturer code.
+        let manufacturer = &rom[0x13F..0x143];
+        let has_manufacturer = cgb_support != CgbSupport::None
+            && manufacturer
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        let (title, manufacturer) = if has_manufacturer {
+            (&rom[0x134..0x13F], Some(ascii(manufacturer)))
+        } else if cgb_support != CgbSupport::None {
+            (&rom[0x134..0x143], None)
+        } else {
+            (&rom[0x134..0x144], None)
+        };
+
+        let old_licensee = rom[0x14B];
+        let rom_size_code = rom[0x148];
+        let ram_size_code = rom[0x149];
+        Ok(CartridgeHeader {
+            title: ascii(title),
+            manufacturer,
+            cgb_support,
+            sgb_support: rom[0x146] == 0x03,
+            old_licensee,
+            new_licensee: if old_licensee == 0x33 {
+                Some(ascii(&rom[0x144..0x146]))
+            } else {
+                None
+            },
+            cartridge_type: CartridgeType::from_code(rom[0x147]),
+            rom_size_code,
+            rom_size: rom_size(rom_size_code),
+            ram_size_code,
+            ram_size: ram_size(ram_size_code),
+            destination: match rom[0x14A] {
+                0x00 => Destination::Japan,
+                0x01 => Destination::Overseas,
+                code => Destination::Unknown(code),
+            },
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            computed_header_checksum: rom[0x134..0x14D]
+                .iter()
+                .fold(0u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1)),
+            global_checksum: (rom[0x14E] as u16) << 8 | rom[0x14F] as u16,
+            computed_global_checksum: rom
+                .iter()
+                .enumerate()
+                .filter(|&(addr, _)| addr != 0x14E && addr != 0x14F)
+                .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16)),
+            logo_valid: rom[0x104..0x134] == NINTENDO_LOGO[..],
+        })
+    }
+
+    // The boot ROM locks up if this doesn't match.
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    // Nothing checks this one, so plenty of real cartridges get it wrong.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+}
+
+// Text up to the first NUL, with anything that isn't printable ASCII
+// replaced.
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| {
+            if c.is_ascii_graphic() || c == b' ' {
+                c as char
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+// 32KB doubled for each step, plus three odd sizes listed in some
+// documentation but never seen on a real cartridge.
+fn rom_size(code: u8) -> Option<usize> {
+    match code {
+        0x00..=0x08 => Some(0x8000 << code),
+        0x52 => Some(72 * 0x4000),
+        0x53 => Some(80 * 0x4000),
+        0x54 => Some(96 * 0x4000),
+        _ => None,
+    }
+}
+
+// MBC2 and MBC7 have their memory built in, and say 0 here.
+fn ram_size(code: u8) -> Option<usize> {
+    match code {
+        0x00 => Some(0),
+        // Listed as 2KB, but no cartridge is known to use it.
+        0x01 => Some(0x800),
+        0x02 => Some(0x2000),
+        0x03 => Some(0x8000),
+        0x04 => Some(0x20000),
+        0x05 => Some(0x10000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_header(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x143] = cgb_flag;
+        rom[0x147] = 0x13;
+        rom[0x148] = 0x05;
+        rom[0x149] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x14D] = 0x42;
+        rom
+    }
+
+    #[test]
+    fn parses_an_old_header() {
+        let header = CartridgeHeader::parse(&rom_with_header(b"POKEMON RED", 0x00)).unwrap();
+        assert_eq!(header.title, "POKEMON RED");
+        assert_eq!(header.manufacturer, None);
+        assert_eq!(header.cgb_support, CgbSupport::None);
+        assert!(!header.sgb_support);
+        assert_eq!(header.new_licensee, Some("01".to_string()));
+        assert_eq!(header.cartridge_type.mapper, Mapper::MBC3);
+        assert!(header.cartridge_type.battery);
+        assert!(!header.cartridge_type.rtc);
+        assert_eq!(header.rom_size, Some(0x100000));
+        assert_eq!(header.ram_size, Some(0x8000));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert!(header.logo_valid);
+    }
+
+    #[test]
+    fn cgb_titles_make_room_for_the_manufacturer() {
+        let header = CartridgeHeader::parse(&rom_with_header(b"POKEMON_SLVAAXE", 0x80)).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer, Some("AAXE".to_string()));
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+
+        let header = CartridgeHeader::parse(&rom_with_header(b"TETRIS DX", 0xC0)).unwrap();
+        assert_eq!(header.title, "TETRIS DX");
+        assert_eq!(header.manufacturer, None);
+        assert_eq!(header.cgb_support, CgbSupport::Only);
+    }
+
+    #[test]
+    fn checksums_are_computed() {
+        let mut rom = rom_with_header(b"TETRIS", 0x00);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid());
+        rom[0x14D] = header.computed_header_checksum;
+        let sum = header.computed_global_checksum - 0x42 + rom[0x14D] as u16;
+        rom[0x14E] = (sum >> 8) as u8;
+        rom[0x14F] = sum as u8;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid());
+        assert!(header.global_checksum_valid());
+    }
+
+    #[test]
+    fn unknown_codes_are_kept() {
+        let mut rom = rom_with_header(b"", 0x00);
+        rom[0x147] = 0x42;
+        rom[0x148] = 0x42;
+        rom[0x104] = 0;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.cartridge_type.mapper, Mapper::Unknown(0x42));
+        assert_eq!(header.rom_size, None);
+        assert!(!header.logo_valid);
+        assert!(CartridgeHeader::parse(&rom[..0x14F]).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+// Everything `gremulator info` reports about a ROM dump, as text for people
+// or JSON for scripts.
+extern crate crc32fast;
+extern crate sha1_smol;
+
+use std::fmt::Write;
+
+use cartridge::header::{CartridgeHeader, CgbSupport, Destination, Mapper};
+use error::EmulatorError;
+use mbc;
+
+pub struct RomInfo {
+    pub header: CartridgeHeader,
+    // What the emulator runs it with, which can differ from the header's
+    // cartridge type.
+    pub mapper: Mapper,
+    pub multicart: bool,
+    pub file_size: usize,
+    pub crc32: u32,
+    pub sha1: String,
+}
+
+impl RomInfo {
+    pub fn new(rom: &[u8]) -> Result<RomInfo, EmulatorError> {
+        let header = CartridgeHeader::parse(rom)?;
+        Ok(RomInfo {
+            mapper: mbc::emulated_mapper(rom, &header),
+            multicart: mbc::is_multicart(rom, &header),
+            header,
+            file_size: rom.len(),
+            crc32: crc32fast::hash(rom),
+            sha1: sha1_smol::Sha1::from(rom).digest().to_string(),
+        })
+    }
+
+    // Whether the file is as big as the header says the ROM is.
+    pub fn size_matches(&self) -> bool {
+        self.header.rom_size == Some(self.file_size)
+    }
+
+    fn emulated_as(&self) -> String {
+        if self.multicart {
+            format!("{} multicart", self.mapper)
+        } else {
+            self.mapper.to_string()
+        }
+    }
+
+    fn built_in_ram(&self) -> Option<&'static str> {
+        mbc::built_in_ram(self.mapper)
+    }
+
+    pub fn to_text(&self) -> String {
+        let header = &self.header;
+        let cartridge_type = &header.cartridge_type;
+        let mut out = String::new();
+        let mut line = |name: &str, value: String| {
+            writeln!(out, "{:<17}{}", format!("{}:", name), value).unwrap();
+        };
+        line("Title", header.title.clone());
+        line(
+            "Manufacturer",
+            header
+                .manufacturer
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+        );
+        line("CGB support", cgb_support(header.cgb_support).to_string());
+        line("SGB support", yes_no(header.sgb_support).to_string());
+        line(
+            "Licensee",
+            match header.new_licensee {
+                Some(ref code) => format!("{} (new)", code),
+                None => format!("${:02x} (old)", header.old_licensee),
+            },
+        );
+        line(
+            "Cartridge type",
+            format!("${:02x}, {}", cartridge_type.code, cartridge_type.mapper),
+        );
+        line("Emulated as", self.emulated_as());
+        line(
+            "RAM",
+            match (self.built_in_ram(), header.ram_size) {
+                (Some(ram), _) => format!("yes, {} built into the {}", ram, self.mapper),
+                (None, Some(size)) if cartridge_type.ram => format!("yes, {} bytes", size),
+                (None, Some(_)) => "no".to_string(),
+                (None, None) => format!("unknown size code ${:02x}", header.ram_size_code),
+            },
+        );
+        line("Battery", yes_no(cartridge_type.battery).to_string());
+        line("RTC", yes_no(cartridge_type.rtc).to_string());
+        line("Rumble", yes_no(cartridge_type.rumble).to_string());
+        line(
+            "ROM size",
+            match header.rom_size {
+                Some(size) => format!(
+                    "{} bytes, file is {} bytes{}",
+                    size,
+                    self.file_size,
+                    if self.size_matches() {
+                        ""
+                    } else {
+                        " (mismatch)"
+                    }
+                ),
+                None => format!("unknown size code ${:02x}", header.rom_size_code),
+            },
+        );
+        line("Destination", destination(header.destination));
+        line("Version", header.version.to_string());
+        line(
+            "Header checksum",
+            format!(
+                "${:02x}, computed ${:02x} ({})",
+                header.header_checksum,
+                header.computed_header_checksum,
+                ok_bad(header.header_checksum_valid())
+            ),
+        );
+        line(
+            "Global checksum",
+            format!(
+                "${:04x}, computed ${:04x} ({})",
+                header.global_checksum,
+                header.computed_global_checksum,
+                ok_bad(header.global_checksum_valid())
+            ),
+        );
+        line("Nintendo logo", ok_bad(header.logo_valid).to_string());
+        line("CRC32", format!("{:08x}", self.crc32));
+        line("SHA-1", self.sha1.clone());
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let header = &self.header;
+        let cartridge_type = &header.cartridge_type;
+        let optional_string = |s: &Option<String>| match *s {
+            Some(ref s) => json_string(s),
+            None => "null".to_string(),
+        };
+        let optional_size = |size: Option<usize>| match size {
+            Some(size) => size.to_string(),
+            None => "null".to_string(),
+        };
+        let fields = [
+            ("title", json_string(&header.title)),
+            ("manufacturer", optional_string(&header.manufacturer)),
+            ("cgb_support", json_string(cgb_support(header.cgb_support))),
+            ("sgb_support", header.sgb_support.to_string()),
+            ("old_licensee", header.old_licensee.to_string()),
+            ("new_licensee", optional_string(&header.new_licensee)),
+            ("cartridge_type", cartridge_type.code.to_string()),
+            ("mapper", json_string(&cartridge_type.mapper.to_string())),
+            ("emulated_mapper", json_string(&self.mapper.to_string())),
+            ("multicart", self.multicart.to_string()),
+            ("ram", cartridge_type.ram.to_string()),
+            (
+                "built_in_ram",
+                match self.built_in_ram() {
+                    Some(ram) => json_string(ram),
+                    None => "null".to_string(),
+                },
+            ),
+            ("battery", cartridge_type.battery.to_string()),
+            ("rtc", cartridge_type.rtc.to_string()),
+            ("rumble", cartridge_type.rumble.to_string()),
+            ("rom_size", optional_size(header.rom_size)),
+            ("ram_size", optional_size(header.ram_size)),
+            ("file_size", self.file_size.to_string()),
+            ("size_matches", self.size_matches().to_string()),
+            ("destination", json_string(&destination(header.destination))),
+            ("version", header.version.to_string()),
+            ("header_checksum", header.header_checksum.to_string()),
+            (
+                "computed_header_checksum",
+                header.computed_header_checksum.to_string(),
+            ),
+            (
+                "header_checksum_valid",
+                header.header_checksum_valid().to_string(),
+            ),
+            ("global_checksum", header.global_checksum.to_string()),
+            (
+                "computed_global_checksum",
+                header.computed_global_checksum.to_string(),
+            ),
+            (
+                "global_checksum_valid",
+                header.global_checksum_valid().to_string(),
+            ),
+            ("logo_valid", header.logo_valid.to_string()),
+            ("crc32", json_string(&format!("{:08x}", self.crc32))),
+            ("sha1", json_string(&self.sha1)),
+        ];
+
+        let mut out = "{\n".to_string();
+        for (i, &(name, ref value)) in fields.iter().enumerate() {
+            let comma = if i + 1 < fields.len() { "," } else { "" };
+            writeln!(out, "  \"{}\": {}{}", name, value, comma).unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn yes_no(val: bool) -> &'static str {
+    if val {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn ok_bad(val: bool) -> &'static str {
+    if val {
+        "ok"
+    } else {
+        "bad"
+    }
+}
+
+fn cgb_support(support: CgbSupport) -> &'static str {
+    match support {
+        CgbSupport::None => "none",
+        CgbSupport::Enhanced => "enhanced",
+        CgbSupport::Only => "only",
+    }
+}
+
+fn destination(destination: Destination) -> String {
+    match destination {
+        Destination::Japan => "Japan".to_string(),
+        Destination::Overseas => "overseas".to_string(),
+        Destination::Unknown(code) => format!("unknown (${:02x})", code),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::header::NINTENDO_LOGO;
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13A].copy_from_slice(b"TE\"ST\\");
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn hashes_the_whole_file() {
+        let info = RomInfo::new(&[0; 0x150]).unwrap();
+        assert_eq!(info.crc32, 0x2d28a891);
+        assert_eq!(info.sha1, "5253eee478649ba9b48ce75d465248229678858d");
+        assert!(!info.size_matches());
+    }
+
+    #[test]
+    fn text_report() {
+        let text = RomInfo::new(&test_rom()).unwrap().to_text();
+        assert!(text.contains("Title:           TE\"ST\\\n"));
+        assert!(text.contains("Cartridge type:  $10, MBC3\n"));
+        assert!(text.contains("RAM:             yes, 32768 bytes\n"));
+        assert!(text.contains("RTC:             yes\n"));
+        assert!(text.contains("ROM size:        32768 bytes, file is 32768 bytes\n"));
+        assert!(text.contains("Nintendo logo:   bad\n"));
+    }
+
+    #[test]
+    fn reports_what_is_emulated() {
+        // MBC2 with an MBC1 multicart's second logo at bank 0x10.
+        let mut rom = vec![0; 0x100000];
+        rom[0x147] = 0x06;
+        let text = RomInfo::new(&rom).unwrap().to_text();
+        assert!(text.contains("Emulated as:     MBC2\n"));
+        assert!(text.contains("RAM:             yes, 512 half bytes built into the MBC2\n"));
+
+        rom[0x147] = 0x01;
+        rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        let info = RomInfo::new(&rom).unwrap();
+        assert!(info.to_text().contains("Cartridge type:  $01, MBC1\n"));
+        assert!(info.to_text().contains("Emulated as:     MBC1 multicart\n"));
+        assert!(info.to_json().contains("  \"multicart\": true,\n"));
+        assert!(info.to_json().contains("  \"built_in_ram\": null,\n"));
+
+        // Mappers that aren't supported run as if there were none.
+        rom[0x147] = 0x20;
+        let text = RomInfo::new(&rom).unwrap().to_text();
+        assert!(text.contains("Emulated as:     ROM only\n"));
+    }
+
+    #[test]
+    fn json_report() {
+        let json = RomInfo::new(&test_rom()).unwrap().to_json();
+        assert!(json.starts_with("{\n  \"title\": \"TE\\\"ST\\\\\",\n"));
+        assert!(json.contains("  \"manufacturer\": null,\n"));
+        assert!(json.contains("  \"mapper\": \"MBC3\",\n"));
+        assert!(json.contains("  \"ram_size\": 32768,\n"));
+        assert!(json.contains("  \"size_matches\": true,\n"));
+        assert!(json.ends_with("\"\n}\n"));
+    }
+}
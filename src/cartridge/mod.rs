@@ -0,0 +1,160 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use self::header::CartridgeHeader;
+use camera::{ImageSource, TestPattern};
+use clock::{Clock, SystemClock};
+use error::EmulatorError;
+
+pub mod header;
+pub mod info;
+
+// The header runs from 0x100 to 0x14F, so anything shorter can't be a real
+// cartridge.
+const MIN_ROM_SIZE: usize = 0x150;
+// Without banking there's nothing to wrap, so 32KB ROMs are allowed to be
+// short. Assemblers often don't pad them out.
+const UNBANKED_ROM_SIZE: usize = 0x8000;
+
+// A cartridge ROM, loaded from a file or from memory, ready to be put into a
+// GameBoy.
+pub struct Cartridge {
+    rom: Vec<u8>,
+    header: CartridgeHeader,
+    // For cartridges with a real time clock
+    clock: Rc<dyn Clock>,
+    // What the Pocket Camera sees
+    camera: Rc<dyn ImageSource>,
+}
+
+impl Cartridge {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Cartridge, EmulatorError> {
+        let path = path.as_ref();
+        // Name the file, since the io error alone doesn't.
+        let rom = fs::read(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        Cartridge::from_bytes(rom)
+    }
+
+    // Takes either a slice or a Vec, which saves a copy.
+    pub fn from_bytes<R: Into<Vec<u8>>>(rom: R) -> Result<Cartridge, EmulatorError> {
+        let rom = rom.into();
+        match rom.len() {
+            0 => Err(EmulatorError::EmptyRom),
+            size if size < MIN_ROM_SIZE => Err(EmulatorError::TruncatedRom {
+                size,
+                header_size: None,
+            }),
+            size => {
+                let header = CartridgeHeader::parse(&rom)?;
+                // Bank numbers past the end would otherwise quietly wrap
+                // around to the start of the ROM.
+                match header.rom_size {
+                    Some(header_size) if header_size > UNBANKED_ROM_SIZE && size < header_size => {
+                        Err(EmulatorError::TruncatedRom {
+                            size,
+                            header_size: Some(header_size),
+                        })
+                    }
+                    _ => Ok(Cartridge {
+                        header,
+                        rom,
+                        clock: Rc::new(SystemClock),
+                        camera: Rc::new(TestPattern),
+                    }),
+                }
+            }
+        }
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Runs the cartridge's clock, if it has one, off something other than
+    // the system time.
+    pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Cartridge {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> Rc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    // Gives the Pocket Camera something other than the test pattern to take
+    // pictures of.
+    pub fn with_camera(mut self, camera: Rc<dyn ImageSource>) -> Cartridge {
+        self.camera = camera;
+        self
+    }
+
+    pub fn camera(&self) -> Rc<dyn ImageSource> {
+        self.camera.clone()
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn into_rom(self) -> Vec<u8> {
+        self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_from_a_file() {
+        let cartridge = Cartridge::from_path("roms/test/ld.gb").unwrap();
+        assert_eq!(cartridge.rom()[0x100], 0x3E);
+    }
+
+    #[test]
+    fn missing_file_names_the_path() {
+        let err = Cartridge::from_path("roms/test/missing.gb").err().unwrap();
+        assert!(matches!(err, EmulatorError::RomLoadError(_)));
+        assert!(err.to_string().contains("roms/test/missing.gb"));
+    }
+
+    #[test]
+    fn empty_and_truncated_roms_are_errors() {
+        assert!(matches!(
+            Cartridge::from_bytes(vec![]),
+            Err(EmulatorError::EmptyRom)
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(&[0; 0x14F][..]),
+            Err(EmulatorError::TruncatedRom {
+                size: 0x14F,
+                header_size: None
+            })
+        ));
+        assert!(Cartridge::from_bytes(&[0; 0x150][..]).is_ok());
+    }
+
+    #[test]
+    fn roms_shorter_than_their_header_says_are_errors() {
+        // 64KB of a 1MB ROM
+        let mut rom = vec![0; 0x10000];
+        rom[0x148] = 0x05;
+        let err = Cartridge::from_bytes(rom).err().unwrap();
+        assert!(matches!(
+            err,
+            EmulatorError::TruncatedRom {
+                size: 0x10000,
+                header_size: Some(0x100000)
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "The ROM is only 65536 bytes, but its header says it's 1048576 bytes"
+        );
+        // Short 32KB ROMs still load, since they don't bank.
+        assert!(Cartridge::from_bytes(&[0; 0x4000][..]).is_ok());
+    }
+}
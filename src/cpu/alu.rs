@@ -1,14 +1,12 @@
-use std::convert::TryInto;
+use register::Flags;
 
-pub fn add(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
-    let result: u16;
+pub fn add(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
     let to_add = val.unwrap_or(*accumulator);
 
     // Not using wrapping_add to check more easily result > 0xff later
-    result = *accumulator as u16 + to_add as u16;
+    let result = *accumulator as u16 + to_add as u16;
 
-    set_flags(
-        flags,
+    flags.set(
         result & 0xff == 0,
         false,
         (((*accumulator & 0xf) + (to_add & 0xf)) & 0x10) == 0x10,
@@ -18,33 +16,30 @@ pub fn add(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
     *accumulator = result as u8;
 }
 
-pub fn add_carry(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
-    let result: u16;
-    let carry = (*flags & 0b00010000) >> 4;
-    let to_add = val.unwrap_or(*accumulator) + carry;
+pub fn add_carry(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
+    let carry = flags.c() as u8;
+    let to_add = val.unwrap_or(*accumulator);
 
-    // Not using wrapping_add to check more easily result > 0xff later
-    result = *accumulator as u16 + to_add as u16;
+    // The carry has to be added separately from the value, otherwise adding
+    // it to 0xff would overflow before the flags can see it.
+    let result = *accumulator as u16 + to_add as u16 + carry as u16;
 
-    set_flags(
-        flags,
+    flags.set(
         result & 0xff == 0,
         false,
-        (((*accumulator & 0xf) + (to_add & 0xf)) & 0x10) == 0x10,
+        (*accumulator & 0xf) + (to_add & 0xf) + carry > 0xf,
         result > 0xff,
     );
 
     *accumulator = result as u8;
 }
 
-pub fn sub(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
-    let result: u8;
+pub fn sub(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
     let to_sub = val.unwrap_or(*accumulator);
 
-    result = (*accumulator).wrapping_sub(to_sub);
+    let result = (*accumulator).wrapping_sub(to_sub);
 
-    set_flags(
-        flags,
+    flags.set(
         result == 0,
         true,
         (*accumulator & 0xf) < (to_sub & 0xf),
@@ -54,100 +49,178 @@ pub fn sub(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
     *accumulator = result;
 }
 
-pub fn and(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
+pub fn sub_carry(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
+    let carry = flags.c() as u8;
+    let to_sub = val.unwrap_or(*accumulator);
+
+    // Same as add_carry, keep the carry separate so borrows are seen.
+    let result = (*accumulator as i16) - (to_sub as i16) - (carry as i16);
+
+    flags.set(
+        result & 0xff == 0,
+        true,
+        ((*accumulator & 0xf) as i16) - ((to_sub & 0xf) as i16) - (carry as i16) < 0,
+        result < 0,
+    );
+
+    *accumulator = result as u8;
+}
+
+pub fn and(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
     let to_and = val.unwrap_or(*accumulator);
-    *accumulator = *accumulator & to_and;
-    set_flags(flags, *accumulator == 0, false, true, false);
+    *accumulator &= to_and;
+    flags.set(*accumulator == 0, false, true, false);
 }
 
-pub fn xor(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
+pub fn xor(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
     let to_xor = val.unwrap_or(*accumulator);
-    *accumulator = *accumulator ^ to_xor;
-    set_flags(flags, *accumulator == 0, false, false, false);
+    *accumulator ^= to_xor;
+    flags.set(*accumulator == 0, false, false, false);
 }
 
-pub fn or(accumulator: &mut u8, val: Option<u8>, flags: &mut u8) {
+pub fn or(accumulator: &mut u8, val: Option<u8>, flags: &mut Flags) {
     let to_or = val.unwrap_or(*accumulator);
-    *accumulator = *accumulator | to_or;
-    set_flags(flags, *accumulator == 0, false, false, false);
+    *accumulator |= to_or;
+    flags.set(*accumulator == 0, false, false, false);
 }
 
-pub fn cp(accumulator: u8, val: Option<u8>, flags: &mut u8) {
+pub fn cp(accumulator: u8, val: Option<u8>, flags: &mut Flags) {
     let mut mutable_accumulator = accumulator;
     sub(&mut mutable_accumulator, val, flags);
 }
 
-pub fn inc(accumulator: &mut u8, flags: &mut u8) {
+pub fn inc(accumulator: &mut u8, flags: &mut Flags) {
     let result = *accumulator as u16 + 1;
 
-    set_flags(
-        flags,
+    flags.set(
         result & 0xff == 0,
         false,
         (((*accumulator & 0xf) + 1) & 0x10) == 0x10,
         // Unchanged
-        ((*flags & 0b00010000) >> 4) == 1,
+        flags.c(),
     );
 
     *accumulator = result as u8;
 }
 
-pub fn dec(accumulator: &mut u8, flags: &mut u8) {
+pub fn dec(accumulator: &mut u8, flags: &mut Flags) {
     let result = (*accumulator).wrapping_sub(1);
 
-    set_flags(
-        flags,
-        result & 0xff == 0,
+    flags.set(
+        result == 0,
         true,
         (*accumulator & 0xf) < 1,
         // Unchanged
-        ((*flags & 0b00010000) >> 4) == 1,
+        flags.c(),
     );
 
-    *accumulator = result as u8;
+    *accumulator = result;
+}
+
+// Rotate left circular - bit 7 goes to both bit 0 and the carry flag.
+pub fn rlc(reg: &mut u8, flags: &mut Flags) {
+    let bit7_set = ((*reg >> 7) & 1) == 1;
+    *reg = reg.rotate_left(1);
+    flags.set(*reg == 0, false, false, bit7_set);
+}
+
+// Rotate right circular - bit 0 goes to both bit 7 and the carry flag.
+pub fn rrc(reg: &mut u8, flags: &mut Flags) {
+    let bit0_set = (*reg & 1) == 1;
+    *reg = reg.rotate_right(1);
+    flags.set(*reg == 0, false, false, bit0_set);
 }
 
-// Rotate left circular - AKA with carry flag in loop.
-pub fn rlc(reg: &mut u8, flags: &mut u8) {
+// Rotate left through carry - AKA with carry flag in loop.
+pub fn rl(reg: &mut u8, flags: &mut Flags) {
     let bit7_set = ((*reg >> 7) & 1) == 1;
-    let prev_c = c_flag(flags);
+    let prev_c = flags.c() as u8;
     *reg = (*reg << 1) | prev_c;
-    set_flags(flags, false, false, false, bit7_set);
+    flags.set(*reg == 0, false, false, bit7_set);
 }
 
-// Rotate right circular - AKA with carry flag in loop.
-pub fn rrc(reg: &mut u8, flags: &mut u8) {
+// Rotate right through carry - AKA with carry flag in loop.
+pub fn rr(reg: &mut u8, flags: &mut Flags) {
     let bit0_set = (*reg & 1) == 1;
-    let prev_c = c_flag(flags);
+    let prev_c = flags.c() as u8;
     *reg = (prev_c << 7) | (*reg >> 1);
-    set_flags(flags, false, false, false, bit0_set);
+    flags.set(*reg == 0, false, false, bit0_set);
+}
+
+// The accumulator rotates (RLCA, RRCA, RLA, RRA) are the same as the CB
+// prefixed ones except the zero flag is always reset.
+pub fn rlca(accumulator: &mut u8, flags: &mut Flags) {
+    rlc(accumulator, flags);
+    flags.set_z(false);
+}
+
+pub fn rrca(accumulator: &mut u8, flags: &mut Flags) {
+    rrc(accumulator, flags);
+    flags.set_z(false);
+}
+
+pub fn rla(accumulator: &mut u8, flags: &mut Flags) {
+    rl(accumulator, flags);
+    flags.set_z(false);
+}
+
+pub fn rra(accumulator: &mut u8, flags: &mut Flags) {
+    rr(accumulator, flags);
+    flags.set_z(false);
 }
 
-// Rotate left - leave carry flag out of rotate, but does get updated.
-pub fn rl(reg: &mut u8, flags: &mut u8) {
+// Shift left arithmetic - bit 7 goes to carry, bit 0 is reset.
+pub fn sla(reg: &mut u8, flags: &mut Flags) {
     let bit7_set = ((*reg >> 7) & 1) == 1;
-    *reg = reg.rotate_left(1);
-    set_flags(flags, false, false, false, bit7_set);
+    *reg <<= 1;
+    flags.set(*reg == 0, false, false, bit7_set);
 }
 
-// Rotate right - leave carry flag out of rotate, but does get updated.
-pub fn rr(reg: &mut u8, flags: &mut u8) {
+// Shift right arithmetic - bit 0 goes to carry, bit 7 is unchanged.
+pub fn sra(reg: &mut u8, flags: &mut Flags) {
     let bit0_set = (*reg & 1) == 1;
-    *reg = reg.rotate_right(1);
-    set_flags(flags, false, false, false, bit0_set);
+    *reg = (*reg >> 1) | (*reg & 0x80);
+    flags.set(*reg == 0, false, false, bit0_set);
+}
+
+// Shift right logical - bit 0 goes to carry, bit 7 is reset.
+pub fn srl(reg: &mut u8, flags: &mut Flags) {
+    let bit0_set = (*reg & 1) == 1;
+    *reg >>= 1;
+    flags.set(*reg == 0, false, false, bit0_set);
+}
+
+// Swap upper and lower nibbles.
+pub fn swap(reg: &mut u8, flags: &mut Flags) {
+    *reg = reg.rotate_left(4);
+    flags.set(*reg == 0, false, false, false);
 }
 
-pub fn daa(accumulator: &mut u8, flags: &mut u8) {
-    // Stolen from https://ehaskins.com/2018-01-30%20Z80%20DAA/
-    // TODO: check this when I feel like using my brain. And add tests.
+// Test a bit - Z is set if the bit is 0, carry is unchanged.
+pub fn bit(reg: u8, bit: u8, flags: &mut Flags) {
+    let bit_set = (reg >> bit) & 1 == 1;
+    flags.set(!bit_set, false, true, flags.c());
+}
+
+// RES and SET don't touch any flags.
+pub fn res(reg: &mut u8, bit: u8) {
+    *reg &= !(1 << bit);
+}
+
+pub fn set(reg: &mut u8, bit: u8) {
+    *reg |= 1 << bit;
+}
+
+pub fn daa(accumulator: &mut u8, flags: &mut Flags) {
+    // Based on https://ehaskins.com/2018-01-30%20Z80%20DAA/
     let mut correction = 0;
-    let prev_h = h_flag(flags) == 1;
-    let prev_n = n_flag(flags) == 1;
+    let prev_h = flags.h();
+    let prev_n = flags.n();
     if prev_h || (!prev_n && (*accumulator & 0xf) > 9) {
         correction |= 0x6;
     }
 
-    let prev_c = c_flag(flags) == 1;
+    let prev_c = flags.c();
     let mut set_c = false;
     if prev_c || (!prev_n && *accumulator > 0x99) {
         correction |= 0x60;
@@ -155,50 +228,58 @@ pub fn daa(accumulator: &mut u8, flags: &mut u8) {
     }
 
     *accumulator = if prev_n {
-        (-1 * correction as i8).try_into().unwrap()
+        accumulator.wrapping_sub(correction)
     } else {
-        correction
+        accumulator.wrapping_add(correction)
     };
 
-    set_flags(flags, *accumulator == 0, prev_n, false, set_c);
+    flags.set(*accumulator == 0, prev_n, false, set_c);
 }
 
-pub fn cpl(accumulator: &mut u8, flags: &mut u8) {
+pub fn cpl(accumulator: &mut u8, flags: &mut Flags) {
     *accumulator = !*accumulator;
-    set_flags(flags, z_flag(flags) == 1, true, true, c_flag(flags) == 1);
+    flags.set(flags.z(), true, true, flags.c());
 }
 
 // Set carry flag
-pub fn scf(flags: &mut u8) {
-    set_flags(flags, z_flag(flags) == 1, false, false, true);
+pub fn scf(flags: &mut Flags) {
+    flags.set(flags.z(), false, false, true);
 }
 
 // Complement carry flag
-pub fn ccf(flags: &mut u8) {
-    // c_flag == 0 -> set
-    // c_flag == 1 -> reset
-    set_flags(flags, z_flag(flags) == 1, false, false, c_flag(flags) == 0);
+pub fn ccf(flags: &mut Flags) {
+    // C reset -> set
+    // C set -> reset
+    flags.set(flags.z(), false, false, !flags.c());
 }
 
-// Utility methods for flags
-fn set_flags(flags: &mut u8, z: bool, n: bool, h: bool, c: bool) {
-    *flags = ((z as u8) << 7) | ((n as u8) << 6) | ((h as u8) << 5) | ((c as u8) << 4);
-}
+// 16 bit add for ADD HL,rr. Z is unchanged, H and C come from bits 11 and 15.
+pub fn add16(reg: &mut u16, val: u16, flags: &mut Flags) {
+    let (result, carry) = reg.overflowing_add(val);
 
-fn c_flag(flags: &u8) -> u8 {
-    (flags >> 4) & 1
-}
+    flags.set(
+        flags.z(),
+        false,
+        (*reg & 0xfff) + (val & 0xfff) > 0xfff,
+        carry,
+    );
 
-fn h_flag(flags: &u8) -> u8 {
-    (flags >> 5) & 1
+    *reg = result;
 }
 
-fn n_flag(flags: &u8) -> u8 {
-    (flags >> 6) & 1
-}
+// SP plus a signed offset, for ADD SP,i8 and LD HL,SP+i8. H and C come from
+// an unsigned add on the low byte, and Z and N are always reset.
+pub fn add_sp_offset(sp: u16, offset: i8, flags: &mut Flags) -> u16 {
+    let unsigned_offset = offset as u8 as u16;
+
+    flags.set(
+        false,
+        false,
+        (sp & 0xf) + (unsigned_offset & 0xf) > 0xf,
+        (sp & 0xff) + unsigned_offset > 0xff,
+    );
 
-fn z_flag(flags: &u8) -> u8 {
-    (flags >> 7) & 1
+    sp.wrapping_add(offset as u16)
 }
 
 #[cfg(test)]
@@ -209,17 +290,17 @@ mod tests {
     fn add_simple_add_calculates_correctly() {
         let mut accumulator = 40;
         let val = 2;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         add(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 42);
         // Also ensure flags don't get set as baseline
-        assert_eq!(flags, 0);
+        assert_eq!(flags.bits(), 0);
     }
 
     #[test]
     fn add_missing_value_adds_accumulator_to_itself() {
         let mut accumulator = 5;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         add(&mut accumulator, None, &mut flags);
         assert_eq!(accumulator, 10);
     }
@@ -228,38 +309,38 @@ mod tests {
     fn add_zero_flag_sets_properly() {
         let mut accumulator = 0;
         let val = 0;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         add(&mut accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b10000000);
+        assert_eq!(flags.bits(), 0b10000000);
     }
 
     #[test]
     fn add_half_carry_flag_sets_properly() {
         let mut accumulator = 0xf;
         let val = 1;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         add(&mut accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b00100000);
+        assert_eq!(flags.bits(), 0b00100000);
     }
 
     #[test]
     fn add_overflow_handles_properly() {
         let mut accumulator = 0xf0;
         let val = 0xf0;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         add(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 224);
-        assert_eq!(flags, 0b00010000);
+        assert_eq!(flags.bits(), 0b00010000);
     }
 
     #[test]
     fn add_carry_to_zero_sets_both_flags() {
         let mut accumulator = 255;
         let val = 1;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         add(&mut accumulator, Some(val), &mut flags);
         println!("{}", accumulator);
-        assert_eq!(flags, 0b10110000);
+        assert_eq!(flags.bits(), 0b10110000);
     }
 
     #[test]
@@ -267,28 +348,38 @@ mod tests {
         let mut accumulator = 40;
         let val = 1;
         // Only carry flag set
-        let mut flags = 0b00010000;
+        let mut flags = Flags::from_bits(0b00010000);
         add_carry(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 42);
         // Flags should now clear.
-        assert_eq!(flags, 0);
+        assert_eq!(flags.bits(), 0);
+    }
+
+    #[test]
+    fn adc_carry_into_overflow_sets_flags() {
+        let mut accumulator = 0;
+        let val = 0xff;
+        let mut flags = Flags::from_bits(0b00010000);
+        add_carry(&mut accumulator, Some(val), &mut flags);
+        assert_eq!(accumulator, 0);
+        assert_eq!(flags.bits(), 0b10110000);
     }
 
     #[test]
     fn sub_simple_subtraction_calculates_correctly() {
         let mut accumulator = 45;
         let val = 3;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         sub(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 42);
         // Also ensure only subtraction flag is set
-        assert_eq!(flags, 0b01000000);
+        assert_eq!(flags.bits(), 0b01000000);
     }
 
     #[test]
     fn sub_missing_value_subtracts_accumulator_from_itself() {
         let mut accumulator = 5;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         sub(&mut accumulator, None, &mut flags);
         assert_eq!(accumulator, 0);
     }
@@ -297,45 +388,69 @@ mod tests {
     fn sub_zero_flag_sets_properly() {
         let mut accumulator = 45;
         let val = 45;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         sub(&mut accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b11000000);
+        assert_eq!(flags.bits(), 0b11000000);
     }
 
     #[test]
     fn sub_half_carry_flag_sets_properly() {
         let mut accumulator = 0x10;
         let val = 10;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         sub(&mut accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b01100000);
+        assert_eq!(flags.bits(), 0b01100000);
     }
 
     #[test]
     fn sub_overflow_handles_properly() {
         let mut accumulator = 0x10;
         let val = 0x20;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         sub(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 240);
-        assert_eq!(flags, 0b01010000);
+        assert_eq!(flags.bits(), 0b01010000);
+    }
+
+    #[test]
+    fn sbc_carry_applies_properly() {
+        let mut accumulator = 45;
+        let val = 2;
+        let mut flags = Flags::from_bits(0b00010000);
+        sub_carry(&mut accumulator, Some(val), &mut flags);
+        assert_eq!(accumulator, 42);
+        assert_eq!(flags.bits(), 0b01000000);
+    }
+
+    #[test]
+    fn sbc_carry_borrows_through_zero() {
+        let mut accumulator = 0x10;
+        let val = 0x0f;
+        let mut flags = Flags::from_bits(0b00010000);
+        sub_carry(&mut accumulator, Some(val), &mut flags);
+        assert_eq!(accumulator, 0);
+        assert_eq!(flags.bits(), 0b11100000);
+
+        sub_carry(&mut accumulator, Some(0xff), &mut flags);
+        assert_eq!(accumulator, 1);
+        assert_eq!(flags.bits(), 0b01110000);
     }
 
     #[test]
     fn and_simple_and_calculates_correctly() {
         let mut accumulator = 0b1111;
         let val = 0b10101010;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         and(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 0b1010);
         // Also ensure flags get baseline set
-        assert_eq!(flags, 0b00100000);
+        assert_eq!(flags.bits(), 0b00100000);
     }
 
     #[test]
     fn and_missing_value_ands_accumulator_with_itself() {
         let mut accumulator = 0b1111;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         and(&mut accumulator, None, &mut flags);
         assert_eq!(accumulator, 0b1111);
     }
@@ -344,28 +459,28 @@ mod tests {
     fn and_zero_flag_sets_properly() {
         let mut accumulator = 0xf;
         let val = 0xf0;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         and(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 0);
         // Also ensure flags get baseline set
-        assert_eq!(flags, 0b10100000);
+        assert_eq!(flags.bits(), 0b10100000);
     }
 
     #[test]
     fn or_simple_or_calculates_correctly() {
         let mut accumulator = 0b01010101;
         let val = 0b10101010;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         or(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 0xff);
         // Also ensure flags get baseline set
-        assert_eq!(flags, 0b00000000);
+        assert_eq!(flags.bits(), 0b00000000);
     }
 
     #[test]
     fn or_missing_value_ors_accumulator_with_itself() {
         let mut accumulator = 0b1111;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         or(&mut accumulator, None, &mut flags);
         assert_eq!(accumulator, 0b1111);
     }
@@ -374,39 +489,39 @@ mod tests {
     fn or_zero_flag_sets_properly() {
         let mut accumulator = 0;
         let val = 0;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         or(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 0);
         // Also ensure flags get baseline set
-        assert_eq!(flags, 0b10000000);
+        assert_eq!(flags.bits(), 0b10000000);
     }
 
     #[test]
     fn cp_does_not_change_accumulator_value() {
         let accumulator = 30;
         let val = 3;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         cp(accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 30);
         // Also ensure only subtraction flag is set
-        assert_eq!(flags, 0b01000000);
+        assert_eq!(flags.bits(), 0b01000000);
     }
 
     #[test]
     fn xor_simple_xor_calculates_correctly() {
         let mut accumulator = 0b01010101;
         let val = 0b11111111;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         xor(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 0b10101010);
         // Also ensure flags get baseline set
-        assert_eq!(flags, 0b00000000);
+        assert_eq!(flags.bits(), 0b00000000);
     }
 
     #[test]
     fn xor_missing_value_xors_accumulator_with_itself() {
         let mut accumulator = 0b1111;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         xor(&mut accumulator, None, &mut flags);
         assert_eq!(accumulator, 0);
     }
@@ -415,193 +530,359 @@ mod tests {
     fn xor_zero_flag_sets_properly() {
         let mut accumulator = 0;
         let val = 0;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         xor(&mut accumulator, Some(val), &mut flags);
         assert_eq!(accumulator, 0);
         // Also ensure flags get baseline set
-        assert_eq!(flags, 0b10000000);
+        assert_eq!(flags.bits(), 0b10000000);
     }
 
     #[test]
     fn cp_missing_value_tests_against_self() {
         let accumulator = 5;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         cp(accumulator, None, &mut flags);
-        assert_eq!(flags, 0b11000000);
+        assert_eq!(flags.bits(), 0b11000000);
     }
 
     #[test]
     fn cp_zero_flag_sets_properly() {
         let accumulator = 45;
         let val = 45;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         cp(accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b11000000);
+        assert_eq!(flags.bits(), 0b11000000);
     }
 
     #[test]
     fn cp_half_carry_flag_sets_properly() {
         let accumulator = 0x10;
         let val = 10;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         cp(accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b01100000);
+        assert_eq!(flags.bits(), 0b01100000);
     }
 
     #[test]
     fn cp_carry_flag_sets_properly() {
         let accumulator = 0x10;
         let val = 0x20;
-        let mut flags = 0;
+        let mut flags = Flags::from_bits(0);
         cp(accumulator, Some(val), &mut flags);
-        assert_eq!(flags, 0b01010000);
+        assert_eq!(flags.bits(), 0b01010000);
     }
 
     #[test]
     fn inc_simple() {
         let mut accumulator = 41;
-        let mut flags = 0b00000000;
+        let mut flags = Flags::from_bits(0b00000000);
         inc(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 42);
         // Flags should now clear.
-        assert_eq!(flags, 0);
+        assert_eq!(flags.bits(), 0);
     }
 
     #[test]
     fn inc_flags() {
         let mut accumulator = 255;
         // Only carry flag set, should stay set no matter what.
-        let mut flags = 0b00010000;
+        let mut flags = Flags::from_bits(0b00010000);
         inc(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0);
         // Flags should now clear.
-        assert_eq!(flags, 0b10110000);
+        assert_eq!(flags.bits(), 0b10110000);
     }
 
     #[test]
     fn dec_simple() {
         let mut accumulator = 43;
-        let mut flags = 0b00000000;
+        let mut flags = Flags::from_bits(0b00000000);
         dec(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 42);
         // Flags should now clear.
-        assert_eq!(flags, 0b01000000);
+        assert_eq!(flags.bits(), 0b01000000);
     }
 
     #[test]
     fn dec_flags() {
         let mut accumulator = 0;
         // Only carry flag set, should stay set no matter what.
-        let mut flags = 0b00010000;
+        let mut flags = Flags::from_bits(0b00010000);
         dec(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 255);
         // Flags should now clear.
-        assert_eq!(flags, 0b01110000);
+        assert_eq!(flags.bits(), 0b01110000);
     }
 
     #[test]
-    fn rlc_no_carry() {
+    fn rl_no_carry() {
         let mut accumulator = 0b11001100;
-        let mut flags = 0b00000000;
-        rlc(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00000000);
+        rl(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b10011000);
         // Carry gets set but don't use it.
-        assert_eq!(flags, 0b00010000);
+        assert_eq!(flags.bits(), 0b00010000);
     }
 
     #[test]
-    fn rlc_carry() {
+    fn rl_carry() {
         let mut accumulator = 0b01010100;
         // Carry flag
-        let mut flags = 0b00010000;
-        rlc(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00010000);
+        rl(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b10101001);
-        assert_eq!(flags, 0b00000000);
+        assert_eq!(flags.bits(), 0b00000000);
     }
 
     #[test]
-    fn rrc_no_carry() {
+    fn rr_no_carry() {
         let mut accumulator = 0b11001101;
-        let mut flags = 0b00000000;
-        rrc(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00000000);
+        rr(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b01100110);
         // Carry gets set but don't use it.
-        assert_eq!(flags, 0b00010000);
+        assert_eq!(flags.bits(), 0b00010000);
     }
 
     #[test]
-    fn rrc_carry() {
+    fn rr_carry() {
         let mut accumulator = 0b01010100;
         // Carry flag
-        let mut flags = 0b00010000;
-        rrc(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00010000);
+        rr(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b10101010);
-        assert_eq!(flags, 0b00000000);
+        assert_eq!(flags.bits(), 0b00000000);
     }
 
     #[test]
-    fn rl_no_carry() {
+    fn rlc_no_carry() {
         let mut accumulator = 0b10101010;
-        let mut flags = 0b00000000;
-        rl(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00000000);
+        rlc(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b01010101);
         // Carry gets set but don't use it.
-        assert_eq!(flags, 0b00010000);
+        assert_eq!(flags.bits(), 0b00010000);
     }
 
     #[test]
-    fn rl_carry() {
+    fn rlc_carry() {
         // Just make sure rotate ignores carries
         let mut accumulator = 0b01010101;
         // Carry flag
-        let mut flags = 0b00010000;
-        rl(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00010000);
+        rlc(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b10101010);
-        assert_eq!(flags, 0b00000000);
+        assert_eq!(flags.bits(), 0b00000000);
     }
 
     #[test]
-    fn rr_no_carry() {
+    fn rrc_no_carry() {
         let mut accumulator = 0b01010101;
-        let mut flags = 0b00000000;
-        rr(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00000000);
+        rrc(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b10101010);
         // Carry gets set but don't use it.
-        assert_eq!(flags, 0b00010000);
+        assert_eq!(flags.bits(), 0b00010000);
     }
 
     #[test]
-    fn rr_carry() {
+    fn rrc_carry() {
         let mut accumulator = 0b10101010;
         // Carry flag
-        let mut flags = 0b00010000;
-        rr(&mut accumulator, &mut flags);
+        let mut flags = Flags::from_bits(0b00010000);
+        rrc(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b01010101);
-        assert_eq!(flags, 0b00000000);
+        assert_eq!(flags.bits(), 0b00000000);
+    }
+
+    #[test]
+    fn rlc_zero_sets_zero_flag() {
+        let mut reg = 0;
+        let mut flags = Flags::from_bits(0);
+        rlc(&mut reg, &mut flags);
+        assert_eq!(reg, 0);
+        assert_eq!(flags.bits(), 0b10000000);
+    }
+
+    #[test]
+    fn rlca_always_resets_zero_flag() {
+        let mut accumulator = 0b10000000;
+        let mut flags = Flags::from_bits(0b10000000);
+        rla(&mut accumulator, &mut flags);
+        assert_eq!(accumulator, 0);
+        assert_eq!(flags.bits(), 0b00010000);
+    }
+
+    #[test]
+    fn sla_shifts_into_carry() {
+        let mut reg = 0b10000001;
+        let mut flags = Flags::from_bits(0);
+        sla(&mut reg, &mut flags);
+        assert_eq!(reg, 0b00000010);
+        assert_eq!(flags.bits(), 0b00010000);
+    }
+
+    #[test]
+    fn sra_keeps_sign_bit() {
+        let mut reg = 0b10000001;
+        let mut flags = Flags::from_bits(0);
+        sra(&mut reg, &mut flags);
+        assert_eq!(reg, 0b11000000);
+        assert_eq!(flags.bits(), 0b00010000);
+    }
+
+    #[test]
+    fn srl_resets_top_bit() {
+        let mut reg = 0b00000001;
+        let mut flags = Flags::from_bits(0);
+        srl(&mut reg, &mut flags);
+        assert_eq!(reg, 0);
+        assert_eq!(flags.bits(), 0b10010000);
+    }
+
+    #[test]
+    fn swap_trivial() {
+        let mut reg = 0xA5;
+        let mut flags = Flags::from_bits(0b01110000);
+        swap(&mut reg, &mut flags);
+        assert_eq!(reg, 0x5A);
+        assert_eq!(flags.bits(), 0);
+    }
+
+    #[test]
+    fn bit_sets_zero_when_bit_clear() {
+        let mut flags = Flags::from_bits(0b00010000);
+        bit(0b11101111, 4, &mut flags);
+        assert_eq!(flags.bits(), 0b10110000);
+        bit(0b00010000, 4, &mut flags);
+        assert_eq!(flags.bits(), 0b00110000);
+    }
+
+    #[test]
+    fn res_and_set_trivial() {
+        let mut reg = 0xFF;
+        res(&mut reg, 7);
+        assert_eq!(reg, 0x7F);
+        set(&mut reg, 7);
+        res(&mut reg, 0);
+        assert_eq!(reg, 0xFE);
+    }
+
+    #[test]
+    fn daa_after_add() {
+        // 0x15 + 0x27 = 0x3c, which should be adjusted to BCD 42
+        let mut accumulator = 0x15;
+        let mut flags = Flags::from_bits(0);
+        add(&mut accumulator, Some(0x27), &mut flags);
+        daa(&mut accumulator, &mut flags);
+        assert_eq!(accumulator, 0x42);
+        assert_eq!(flags.bits(), 0b00000000);
+    }
+
+    #[test]
+    fn daa_after_add_with_carry_out() {
+        let mut accumulator = 0x99;
+        let mut flags = Flags::from_bits(0);
+        add(&mut accumulator, Some(0x01), &mut flags);
+        daa(&mut accumulator, &mut flags);
+        assert_eq!(accumulator, 0x00);
+        assert_eq!(flags.bits(), 0b10010000);
+    }
+
+    #[test]
+    fn daa_after_sub() {
+        let mut accumulator = 0x42;
+        let mut flags = Flags::from_bits(0);
+        sub(&mut accumulator, Some(0x15), &mut flags);
+        daa(&mut accumulator, &mut flags);
+        assert_eq!(accumulator, 0x27);
+        assert_eq!(flags.bits(), 0b01000000);
     }
 
     #[test]
     fn cpl_trivial() {
         let mut accumulator = 0b10101010;
-        let mut flags = 0b10010000;
+        let mut flags = Flags::from_bits(0b10010000);
         cpl(&mut accumulator, &mut flags);
         assert_eq!(accumulator, 0b01010101);
-        assert_eq!(flags, 0b11110000);
+        assert_eq!(flags.bits(), 0b11110000);
     }
 
     #[test]
     fn scf_trivial() {
-        let mut flags = 0b10100000;
+        let mut flags = Flags::from_bits(0b10100000);
         scf(&mut flags);
-        assert_eq!(flags, 0b10010000);
+        assert_eq!(flags.bits(), 0b10010000);
     }
 
     #[test]
     fn ccf_trivial() {
-        let mut flags = 0b10110000;
+        let mut flags = Flags::from_bits(0b10110000);
         ccf(&mut flags);
-        assert_eq!(flags, 0b10000000);
+        assert_eq!(flags.bits(), 0b10000000);
         ccf(&mut flags);
-        assert_eq!(flags, 0b10010000);
+        assert_eq!(flags.bits(), 0b10010000);
+    }
+
+    #[test]
+    fn add16_simple_add_calculates_correctly() {
+        let mut reg = 0x1234;
+        let mut flags = Flags::from_bits(0);
+        add16(&mut reg, 0x0101, &mut flags);
+        assert_eq!(reg, 0x1335);
+        assert_eq!(flags.bits(), 0);
+    }
+
+    #[test]
+    fn add16_half_carry_comes_from_bit_11() {
+        let mut reg = 0x0fff;
+        let mut flags = Flags::from_bits(0);
+        add16(&mut reg, 1, &mut flags);
+        assert_eq!(reg, 0x1000);
+        assert_eq!(flags.bits(), 0b00100000);
+    }
+
+    #[test]
+    fn add16_overflow_sets_carry_and_keeps_zero() {
+        let mut reg = 0xffff;
+        // Z and N set beforehand
+        let mut flags = Flags::from_bits(0b11000000);
+        add16(&mut reg, 1, &mut flags);
+        assert_eq!(reg, 0);
+        assert_eq!(flags.bits(), 0b10110000);
+    }
+
+    #[test]
+    fn add16_no_half_carry_from_low_byte() {
+        let mut reg = 0x00ff;
+        let mut flags = Flags::from_bits(0);
+        add16(&mut reg, 1, &mut flags);
+        assert_eq!(reg, 0x0100);
+        assert_eq!(flags.bits(), 0);
+    }
+
+    #[test]
+    fn add_sp_offset_positive() {
+        let mut flags = Flags::from_bits(0b11000000);
+        assert_eq!(add_sp_offset(0xfff8, 2, &mut flags), 0xfffa);
+        // Z and N always reset
+        assert_eq!(flags.bits(), 0);
+    }
+
+    #[test]
+    fn add_sp_offset_flags_come_from_low_byte() {
+        let mut flags = Flags::from_bits(0);
+        assert_eq!(add_sp_offset(0x00ff, 1, &mut flags), 0x0100);
+        assert_eq!(flags.bits(), 0b00110000);
+    }
+
+    #[test]
+    fn add_sp_offset_negative() {
+        let mut flags = Flags::from_bits(0);
+        // -1 is added as 0xff to the low byte, so both carries are set.
+        assert_eq!(add_sp_offset(0x0001, -1, &mut flags), 0x0000);
+        assert_eq!(flags.bits(), 0b00110000);
+        assert_eq!(add_sp_offset(0x0000, -1, &mut flags), 0xffff);
+        assert_eq!(flags.bits(), 0);
     }
 }
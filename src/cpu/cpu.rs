@@ -1,1161 +1,1013 @@
 extern crate log;
 
-use std::io::Error;
+use self::log::{info, trace, warn};
 
-use self::log::{info, trace};
+use cpu::instruction::{Condition, Instruction, Operand8, Operation, Reg16, Reg8};
+use error::EmulatorError;
+use interrupt::Interrupt;
+use mmu::MMU;
+use model::Model;
+use register::Flags;
 
+// What to do on one of the 11 opcodes that don't exist on the SM83.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IllegalOpcodeMode {
+    // Stop and return an IllegalOpcode error from cycle.
+    Error,
+    // Hang like the hardware does. Nothing, not even an interrupt, gets the
+    // CPU out of this.
+    Lock,
+}
+
+// The SM83 core. It doesn't own any memory, everything goes through the bus
+// it's given, which lets the GameBoy hold on to both.
 pub struct CPU {
     pub registers: ::register::Registers,
-    mmu: ::mmu::MMU,
     pub halted: bool,
+    // Low power mode entered by STOP, left when a joypad line goes low.
+    pub stopped: bool,
+    pub illegal_opcode_mode: IllegalOpcodeMode,
+    // Set after running an illegal opcode in IllegalOpcodeMode::Lock.
+    pub locked: bool,
+    // Interrupt master enable
+    pub ime: bool,
+    // EI only takes effect after the instruction following it.
+    ime_pending: bool,
+    // HALT with IME off and an interrupt already pending doesn't halt, but
+    // makes the CPU read the next byte twice.
+    halt_bug: bool,
+    // Cycles used so far by the current call to cycle.
+    cycles: u8,
 }
 
 impl CPU {
-    pub fn new() -> Result<CPU, Error> {
-        info!("Created new CPU");
-        let mmu = ::mmu::MMU::new()?;
-        Ok(CPU {
-            registers: ::register::Registers::new(),
-            mmu: mmu,
+    // Starts in the state the model's boot ROM leaves things in, just before
+    // jumping to the cartridge at 0x100.
+    pub fn new(model: Model, header_checksum: u8) -> CPU {
+        info!("Created new {:?} CPU", model);
+        CPU {
+            registers: model.registers(header_checksum),
             halted: false,
-        })
+            stopped: false,
+            illegal_opcode_mode: IllegalOpcodeMode::Error,
+            locked: false,
+            ime: false,
+            ime_pending: false,
+            halt_bug: false,
+            cycles: 0,
+        }
     }
 
-    pub fn fetch_byte(&mut self) -> u8 {
-        let byte = self.mmu.fetch(self.registers.pc);
-        self.registers.pc += 1;
-        byte
+    // Decodes the instruction at an address without executing it, or letting
+    // any time pass.
+    pub fn decode_at(&self, mmu: &MMU, addr: u16) -> Result<Instruction, EmulatorError> {
+        let mut error = None;
+        let instruction = Instruction::decode(addr, |addr| match mmu.fetch(addr) {
+            Ok(byte) => byte,
+            Err(err) => {
+                error.get_or_insert(err);
+                0
+            }
+        });
+        match error {
+            Some(err) => Err(err),
+            None => Ok(instruction),
+        }
     }
 
-    pub fn cycle(&mut self) -> u8 {
-        let opcode = self.fetch_byte();
-        trace!("Cycle on opcode {}", opcode);
-        self.ops(opcode)
-    }
+    // Runs one instruction, or dispatches one interrupt, and returns the
+    // number of cycles taken. The rest of the system is advanced as the CPU
+    // goes, one M-cycle per memory access or internal delay.
+    pub fn cycle(&mut self, mmu: &mut MMU) -> Result<u8, EmulatorError> {
+        self.cycles = 0;
+        if self.stopped {
+            if !mmu.joypad_line_low() {
+                // The clock is stopped, so nothing else runs either.
+                return Ok(4);
+            }
+            info!("CPU leaving STOP");
+            self.stopped = false;
+        }
 
-    // All operations
-    pub fn ops(&mut self, opcode: u8) -> u8 {
-        // Copy registers to avoid immutable/mutable reference with ALU ops
-        let copy_registers = self.registers;
-        match opcode {
-            // NOP
-            0x00 => 4,
-            // LD BC,u16
-            0x01 => {
-                let immediate = ((self.fetch_byte() as u16) << 8) + (self.fetch_byte() as u16);
-                self.registers.set_bc(immediate);
-                12
-            }
-            // LD (BC), A
-            0x02 => {
-                // TODO
-                8
-            }
-            // INC BC
-            0x03 => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.set_bc(self.registers.bc().wrapping_add(1));
-                8
-            }
-            // INC B
-            0x04 => {
-                ::cpu::alu::inc(&mut self.registers.b, &mut self.registers.f);
-                4
-            }
-            // DEC B
-            0x05 => {
-                ::cpu::alu::dec(&mut self.registers.b, &mut self.registers.f);
-                4
-            }
-            // LD B,n
-            0x06 => {
-                self.registers.b = self.fetch_byte();
-                8
-            }
-            // RLCA
-            0x07 => {
-                ::cpu::alu::rlc(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // LD (u16), SP
-            0x08 => {
-                // TODO
-                20
-            }
-            // ADD HL, BC
-            0x09 => {
-                // TODO
-                8
-            }
-            // LD A,(BC)
-            0x0A => {
-                // TODO
-                8
-            }
-            // DEC BC
-            0x0B => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.set_bc(self.registers.bc().wrapping_sub(1));
-                8
-            }
-            // INC C
-            0x0C => {
-                ::cpu::alu::inc(&mut self.registers.c, &mut self.registers.f);
-                4
-            }
-            // DEC C
-            0x0D => {
-                ::cpu::alu::dec(&mut self.registers.c, &mut self.registers.f);
-                4
-            }
-            // LD C,n
-            0x0E => {
-                self.registers.c = self.fetch_byte();
-                8
-            }
-            // RRCA
-            0x0F => {
-                ::cpu::alu::rrc(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // LD DE,u16
-            0x11 => {
-                let immediate = ((self.fetch_byte() as u16) << 8) + (self.fetch_byte() as u16);
-                self.registers.set_de(immediate);
-                12
-            }
-            // LD (DE),A
-            0x12 => {
-                // TODO
-                8
-            }
-            // INC DE
-            0x13 => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.set_de(self.registers.de().wrapping_add(1));
-                8
-            }
-            // INC D
-            0x14 => {
-                ::cpu::alu::inc(&mut self.registers.d, &mut self.registers.f);
-                4
-            }
-            // DEC D
-            0x15 => {
-                ::cpu::alu::dec(&mut self.registers.d, &mut self.registers.f);
-                4
-            }
-            // LD D,n
-            0x16 => {
-                self.registers.d = self.fetch_byte();
-                8
-            }
-            // RLA
-            0x17 => {
-                ::cpu::alu::rl(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // JR i8
-            0x18 => {
-                // TODO
-                12
-            }
-            // ADD HL, DE
-            0x19 => {
-                // TODO
-                8
-            }
-            // LD A,(DE)
-            0x1A => {
-                // TODO
-                8
-            }
-            // DEC BC
-            0x1B => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.set_de(self.registers.de().wrapping_sub(1));
-                8
-            }
-            // INC E
-            0x1C => {
-                ::cpu::alu::inc(&mut self.registers.e, &mut self.registers.f);
-                4
-            }
-            // DEC E
-            0x1D => {
-                ::cpu::alu::dec(&mut self.registers.e, &mut self.registers.f);
-                4
-            }
-            // LD E,n
-            0x1E => {
-                self.registers.e = self.fetch_byte();
-                8
-            }
-            // RRA
-            0x1F => {
-                ::cpu::alu::rr(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // LD HL,u16
-            0x21 => {
-                let immediate = ((self.fetch_byte() as u16) << 8) + (self.fetch_byte() as u16);
-                self.registers.set_hl(immediate);
-                12
-            }
-            // LD (HL+),A
-            0x22 => {
-                // TODO
-                8
-            }
-            // INC HL
-            0x23 => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.set_hl(self.registers.hl().wrapping_add(1));
-                8
-            }
-            // INC H
-            0x24 => {
-                ::cpu::alu::inc(&mut self.registers.h, &mut self.registers.f);
-                4
-            }
-            // DEC H
-            0x25 => {
-                ::cpu::alu::dec(&mut self.registers.h, &mut self.registers.f);
-                4
-            }
-            // LD H,n
-            0x26 => {
-                self.registers.h = self.fetch_byte();
-                8
-            }
-            // DAA
-            0x27 => {
-                ::cpu::alu::daa(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // JR Z,i8
-            0x28 => {
-                // TODO
-                // Note: time is 8-12 - determine which
-                8
-            }
-            // ADD HL, HL
-            0x29 => {
-                // TODO
-                8
-            }
-            // LD A,(HL+)
-            0x2A => {
-                // TODO
-                8
-            }
-            // DEC HL
-            0x2B => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.set_hl(self.registers.hl().wrapping_sub(1));
-                8
-            }
-            // INC L
-            0x2C => {
-                ::cpu::alu::inc(&mut self.registers.l, &mut self.registers.f);
-                4
-            }
-            // DEC L
-            0x2D => {
-                ::cpu::alu::dec(&mut self.registers.e, &mut self.registers.f);
-                4
-            }
-            // LD L,n
-            0x2E => {
-                self.registers.l = self.fetch_byte();
-                8
-            }
-            // CPL
-            0x2F => {
-                ::cpu::alu::cpl(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // LD SP,u16
-            0x31 => {
-                let immediate = ((self.fetch_byte() as u16) << 8) + (self.fetch_byte() as u16);
-                self.registers.sp = immediate;
-                12
-            }
-            // LD (HL-),A
-            0x32 => {
-                // TODO
-                8
-            }
-            // INC SP
-            0x33 => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.sp = self.registers.sp.wrapping_add(1);
-                8
-            }
-            // INC (HL)
-            0x34 => {
-                // TODO: Is there a better way?
-                let mem_addr = self.registers.hl();
-                let mut res = self.mmu.fetch(mem_addr);
-                ::cpu::alu::inc(&mut res, &mut self.registers.f);
-                self.mmu.set_mem_addr(mem_addr, res);
-                12
-            }
-            // DEC (HL)
-            0x35 => {
-                // TODO: Is there a better way?
-                let mem_addr = self.registers.hl();
-                let mut res = self.mmu.fetch(mem_addr);
-                ::cpu::alu::dec(&mut res, &mut self.registers.f);
-                self.mmu.set_mem_addr(mem_addr, res);
-                12
-            }
-            // LD (HL),n
-            0x36 => {
-                let val = self.fetch_byte();
-                self.mmu.set_mem_addr(self.registers.hl(), val);
-                12
-            }
-            // SCF
-            0x37 => {
-                ::cpu::alu::scf(&mut self.registers.f);
-                4
-            }
-            // JR C,i8
-            0x38 => {
-                // TODO
-                // Note: time is 8-12 - determine which
-                8
-            }
-            // ADD HL, SP
-            0x39 => {
-                // TODO
-                8
-            }
-            // LD A,(HL-)
-            0x3A => {
-                // TODO
-                8
-            }
-            // DEC SP
-            0x3B => {
-                // Does not update flags, so don't need to go through ALU.
-                self.registers.sp = self.registers.sp.wrapping_sub(1);
-                8
-            }
-            // INC A
-            0x3C => {
-                ::cpu::alu::inc(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // DEC A
-            0x3D => {
-                ::cpu::alu::dec(&mut self.registers.a, &mut self.registers.f);
-                4
-            }
-            // LD A,n
-            0x3E => {
-                self.registers.a = self.fetch_byte();
-                8
-            }
-            // CCF
-            0x3F => {
-                ::cpu::alu::ccf(&mut self.registers.f);
-                4
-            }
-            // LD B,B
-            0x40 => {
-                self.registers.b = self.registers.b;
-                4
-            }
-            // LD B,C
-            0x41 => {
-                self.registers.b = self.registers.c;
-                4
-            }
-            // LD B,D
-            0x42 => {
-                self.registers.b = self.registers.d;
-                4
-            }
-            // LD B,E
-            0x43 => {
-                self.registers.b = self.registers.e;
-                4
-            }
-            // LD B,H
-            0x44 => {
-                self.registers.b = self.registers.h;
-                4
-            }
-            // LD B,L
-            0x45 => {
-                self.registers.b = self.registers.l;
-                4
-            }
-            // LD B,(HL)
-            0x46 => {
-                self.registers.b = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD C,B
-            0x48 => {
-                self.registers.c = self.registers.b;
-                4
-            }
-            // LD C,C
-            0x49 => {
-                self.registers.c = self.registers.c;
-                4
-            }
-            // LD C,D
-            0x4A => {
-                self.registers.c = self.registers.d;
-                4
-            }
-            // LD C,E
-            0x4B => {
-                self.registers.c = self.registers.e;
-                4
-            }
-            // LD C,H
-            0x4C => {
-                self.registers.c = self.registers.h;
-                4
-            }
-            // LD C,L
-            0x4D => {
-                self.registers.c = self.registers.l;
-                4
-            }
-            // LD C,(HL)
-            0x4E => {
-                self.registers.c = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD D,B
-            0x50 => {
-                self.registers.d = self.registers.b;
-                4
-            }
-            // LD D,C
-            0x51 => {
-                self.registers.d = self.registers.c;
-                4
-            }
-            // LD D,D
-            0x52 => {
-                self.registers.d = self.registers.d;
-                4
-            }
-            // LD D,E
-            0x53 => {
-                self.registers.d = self.registers.e;
-                4
-            }
-            // LD D,H
-            0x54 => {
-                self.registers.d = self.registers.h;
-                4
-            }
-            // LD D,L
-            0x55 => {
-                self.registers.d = self.registers.l;
-                4
-            }
-            // LD D,(HL)
-            0x56 => {
-                self.registers.d = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD E,B
-            0x58 => {
-                self.registers.e = self.registers.b;
-                4
-            }
-            // LD E,C
-            0x59 => {
-                self.registers.e = self.registers.c;
-                4
-            }
-            // LD E,D
-            0x5A => {
-                self.registers.e = self.registers.d;
-                4
-            }
-            // LD E,E
-            0x5B => {
-                self.registers.e = self.registers.e;
-                4
-            }
-            // LD E,H
-            0x5C => {
-                self.registers.e = self.registers.h;
-                4
-            }
-            // LD E,L
-            0x5D => {
-                self.registers.e = self.registers.l;
-                4
-            }
-            // LD E,(HL)
-            0x5E => {
-                self.registers.e = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD H,B
-            0x60 => {
-                self.registers.h = self.registers.b;
-                4
-            }
-            // LD H,C
-            0x61 => {
-                self.registers.h = self.registers.c;
-                4
-            }
-            // LD H,D
-            0x62 => {
-                self.registers.h = self.registers.d;
-                4
-            }
-            // LD H,E
-            0x63 => {
-                self.registers.h = self.registers.e;
-                4
-            }
-            // LD H,H
-            0x64 => {
-                self.registers.h = self.registers.h;
-                4
-            }
-            // LD H,L
-            0x65 => {
-                self.registers.h = self.registers.l;
-                4
-            }
-            // LD H,(HL)
-            0x66 => {
-                self.registers.h = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD L,B
-            0x68 => {
-                self.registers.l = self.registers.b;
-                4
-            }
-            // LD L,C
-            0x69 => {
-                self.registers.l = self.registers.c;
-                4
-            }
-            // LD L,D
-            0x6A => {
-                self.registers.l = self.registers.d;
-                4
-            }
-            // LD L,E
-            0x6B => {
-                self.registers.l = self.registers.e;
-                4
-            }
-            // LD L,H
-            0x6C => {
-                self.registers.l = self.registers.h;
-                4
-            }
-            // LD L,L
-            0x6D => {
-                self.registers.l = self.registers.l;
-                4
-            }
-            // LD L,(HL)
-            0x6E => {
-                self.registers.l = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD (HL),B
-            0x70 => {
-                self.mmu.set_mem_addr(self.registers.hl(), self.registers.b);
-                8
-            }
-            // LD (HL),C
-            0x71 => {
-                self.mmu.set_mem_addr(self.registers.hl(), self.registers.c);
-                8
-            }
-            // LD (HL),D
-            0x72 => {
-                self.mmu.set_mem_addr(self.registers.hl(), self.registers.d);
-                self.registers.h = self.registers.d;
-                8
-            }
-            // LD (HL),E
-            0x73 => {
-                self.mmu.set_mem_addr(self.registers.hl(), self.registers.e);
-                8
-            }
-            // LD (HL),H
-            0x74 => {
-                self.mmu.set_mem_addr(self.registers.hl(), self.registers.h);
-                8
-            }
-            // LD (HL),L
-            0x75 => {
-                self.mmu.set_mem_addr(self.registers.hl(), self.registers.l);
-                8
-            }
-            // HALT
-            0x76 => {
-                info!("CPU halting");
-                self.halted = true;
-                4
-            }
-            // LD A,B
-            0x78 => {
-                self.registers.a = self.registers.b;
-                4
-            }
-            // LD A,C
-            0x79 => {
-                self.registers.a = self.registers.c;
-                4
-            }
-            // LD A,D
-            0x7A => {
-                self.registers.a = self.registers.d;
-                4
-            }
-            // LD A,E
-            0x7B => {
-                self.registers.a = self.registers.e;
-                4
-            }
-            // LD A,H
-            0x7C => {
-                self.registers.a = self.registers.h;
-                4
-            }
-            // LD A,L
-            0x7D => {
-                self.registers.a = self.registers.l;
-                4
-            }
-            // LD A,(HL)
-            0x7E => {
-                self.registers.a = self.mmu.fetch(self.registers.hl());
-                8
-            }
-            // LD A,A
-            0x7F => {
-                self.registers.a = self.registers.a;
-                4
-            }
-            // ADD A,B
-            0x80 => {
-                ::cpu::alu::add(
-                    &mut self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADD A,C
-            0x81 => {
-                ::cpu::alu::add(
-                    &mut self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADD A,D
-            0x82 => {
-                ::cpu::alu::add(
-                    &mut self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADD A,E
-            0x83 => {
-                ::cpu::alu::add(
-                    &mut self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADD A,H
-            0x84 => {
-                ::cpu::alu::add(
-                    &mut self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADD A,L
-            0x85 => {
-                ::cpu::alu::add(
-                    &mut self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADD A,[HL]
-            0x86 => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::add(&mut self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // ADD A,A
-            0x87 => {
-                ::cpu::alu::add(&mut self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // ADC A,B
-            0x88 => {
-                ::cpu::alu::add_carry(
-                    &mut self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADC A,C
-            0x89 => {
-                ::cpu::alu::add_carry(
-                    &mut self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADC A,D
-            0x8A => {
-                ::cpu::alu::add_carry(
-                    &mut self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADC A,E
-            0x8B => {
-                ::cpu::alu::add_carry(
-                    &mut self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADC A,H
-            0x8C => {
-                ::cpu::alu::add_carry(
-                    &mut self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADC A,L
-            0x8D => {
-                ::cpu::alu::add_carry(
-                    &mut self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // ADC A,[HL]
-            0x8E => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::add_carry(&mut self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // ADC A,A
-            0x8F => {
-                ::cpu::alu::add_carry(&mut self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // SUB A,B
-            0x90 => {
-                ::cpu::alu::sub(
-                    &mut self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // SUB A,C
-            0x91 => {
-                ::cpu::alu::sub(
-                    &mut self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // SUB A,D
-            0x92 => {
-                ::cpu::alu::sub(
-                    &mut self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // SUB A,E
-            0x93 => {
-                ::cpu::alu::sub(
-                    &mut self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // SUB A,H
-            0x94 => {
-                ::cpu::alu::sub(
-                    &mut self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // SUB A,L
-            0x95 => {
-                ::cpu::alu::sub(
-                    &mut self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // SUB A,[HL]
-            0x96 => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::sub(&mut self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // SUB A,A
-            0x97 => {
-                ::cpu::alu::sub(&mut self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // TODO: SBC
-            // AND A,B
-            0xA0 => {
-                ::cpu::alu::and(
-                    &mut self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // AND A,C
-            0xA1 => {
-                ::cpu::alu::and(
-                    &mut self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // AND A,D
-            0xA2 => {
-                ::cpu::alu::and(
-                    &mut self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // AND A,E
-            0xA3 => {
-                ::cpu::alu::and(
-                    &mut self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // AND A,H
-            0xA4 => {
-                ::cpu::alu::and(
-                    &mut self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // AND A,L
-            0xA5 => {
-                ::cpu::alu::and(
-                    &mut self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // AND A,[HL]
-            0xA6 => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::and(&mut self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // AND A,A
-            0xA7 => {
-                ::cpu::alu::and(&mut self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // XOR A,B
-            0xA8 => {
-                ::cpu::alu::xor(
-                    &mut self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // XOR A,C
-            0xA9 => {
-                ::cpu::alu::xor(
-                    &mut self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // XOR A,D
-            0xAA => {
-                ::cpu::alu::xor(
-                    &mut self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // XOR A,E
-            0xAB => {
-                ::cpu::alu::xor(
-                    &mut self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // XOR A,H
-            0xAC => {
-                ::cpu::alu::xor(
-                    &mut self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // XOR A,L
-            0xAD => {
-                ::cpu::alu::xor(
-                    &mut self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // XOR A,[HL]
-            0xAE => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::xor(&mut self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // XOR A,A
-            0xAF => {
-                ::cpu::alu::xor(&mut self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // OR A,B
-            0xB0 => {
-                ::cpu::alu::or(
-                    &mut self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // OR A,C
-            0xB1 => {
-                ::cpu::alu::or(
-                    &mut self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // OR A,D
-            0xB2 => {
-                ::cpu::alu::or(
-                    &mut self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // OR A,E
-            0xB3 => {
-                ::cpu::alu::or(
-                    &mut self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // OR A,H
-            0xB4 => {
-                ::cpu::alu::or(
-                    &mut self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // OR A,L
-            0xB5 => {
-                ::cpu::alu::or(
-                    &mut self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // OR A,[HL]
-            0xB6 => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::or(&mut self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // OR A,A
-            0xB7 => {
-                ::cpu::alu::or(&mut self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // CP A,B
-            0xB8 => {
-                ::cpu::alu::cp(
-                    self.registers.a,
-                    Some(self.registers.b),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // CP A,C
-            0xB9 => {
-                ::cpu::alu::cp(
-                    self.registers.a,
-                    Some(self.registers.c),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // CP A,D
-            0xBA => {
-                ::cpu::alu::cp(
-                    self.registers.a,
-                    Some(self.registers.d),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // CP A,E
-            0xBB => {
-                ::cpu::alu::cp(
-                    self.registers.a,
-                    Some(self.registers.e),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // CP A,H
-            0xBC => {
-                ::cpu::alu::cp(
-                    self.registers.a,
-                    Some(self.registers.h),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // CP A,L
-            0xBD => {
-                ::cpu::alu::cp(
-                    self.registers.a,
-                    Some(self.registers.l),
-                    &mut self.registers.f,
-                );
-                4
-            }
-            // CP A,[HL]
-            0xBE => {
-                let mem_val = self.mmu.fetch(copy_registers.hl());
-                ::cpu::alu::cp(self.registers.a, Some(mem_val), &mut self.registers.f);
-                8
-            }
-            // CP A,A
-            0xBF => {
-                ::cpu::alu::cp(self.registers.a, None, &mut self.registers.f);
-                4
-            }
-            // ADD A,#
-            0xC6 => {
-                let byte = self.fetch_byte();
-                ::cpu::alu::add(&mut self.registers.a, Some(byte), &mut self.registers.f);
-                8
-            }
-            // TODO: ADC
-            // SUB A,#
-            0xD6 => {
-                let byte = self.fetch_byte();
-                ::cpu::alu::sub(&mut self.registers.a, Some(byte), &mut self.registers.f);
-                8
-            }
-            // TODO: SBC
-            // AND A,#
-            0xE6 => {
-                let byte = self.fetch_byte();
-                ::cpu::alu::and(&mut self.registers.a, Some(byte), &mut self.registers.f);
-                8
+        // A locked CPU just burns cycles until it's reset.
+        if self.locked {
+            self.tick(mmu);
+            return Ok(self.cycles);
+        }
+
+        // Any pending interrupt wakes the CPU from HALT, even with IME off.
+        if self.halted {
+            if mmu.pending_interrupts() == 0 {
+                self.tick(mmu);
+                return Ok(self.cycles);
             }
-            // XOR A,#
-            0xEE => {
-                let byte = self.fetch_byte();
-                ::cpu::alu::xor(&mut self.registers.a, Some(byte), &mut self.registers.f);
-                8
+            self.halted = false;
+        }
+
+        if self.ime && self.handle_interrupt(mmu)? {
+            return Ok(self.cycles);
+        }
+
+        let enable_ime = self.ime_pending;
+        let instruction = self.fetch_instruction(mmu)?;
+        trace!("Cycle on instruction {}", instruction);
+        self.execute(mmu, &instruction)?;
+        debug_assert!(
+            self.cycles == instruction.cycles || self.cycles == instruction.branch_cycles,
+            "{} took {} cycles",
+            instruction,
+            self.cycles
+        );
+        // DI right after EI cancels it, so check it's still pending.
+        if enable_ime && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        Ok(self.cycles)
+    }
+
+    // One M-cycle passes.
+    fn tick(&mut self, mmu: &mut MMU) {
+        mmu.tick();
+        self.cycles += 4;
+    }
+
+    fn read(&mut self, mmu: &mut MMU, addr: u16) -> Result<u8, EmulatorError> {
+        self.tick(mmu);
+        mmu.fetch(addr)
+    }
+
+    fn write(&mut self, mmu: &mut MMU, addr: u16, val: u8) -> Result<(), EmulatorError> {
+        self.tick(mmu);
+        mmu.set_mem_addr(addr, val)
+    }
+
+    // Decodes the instruction at PC, one M-cycle per byte fetched, and moves
+    // PC past it.
+    fn fetch_instruction(&mut self, mmu: &mut MMU) -> Result<Instruction, EmulatorError> {
+        let pc = self.registers.pc;
+        // With the HALT bug PC isn't incremented after reading the opcode,
+        // so the opcode byte is read again as the first byte after it.
+        let halt_bug = self.halt_bug;
+        self.halt_bug = false;
+
+        let cycles = &mut self.cycles;
+        let mut error = None;
+        let mut opcode = None;
+        let instruction = Instruction::decode(pc, |addr| {
+            // STOP's second byte is skipped over rather than fetched.
+            if addr == pc || opcode != Some(0x10) {
+                mmu.tick();
+                *cycles += 4;
+            }
+            let read_addr = if halt_bug && addr != pc {
+                addr.wrapping_sub(1)
+            } else {
+                addr
+            };
+            match mmu.fetch(read_addr) {
+                Ok(byte) => {
+                    opcode.get_or_insert(byte);
+                    byte
+                }
+                Err(err) => {
+                    error.get_or_insert(err);
+                    0
+                }
+            }
+        });
+        if let Some(err) = error {
+            return Err(err);
+        }
+
+        let length = if halt_bug {
+            instruction.length - 1
+        } else {
+            instruction.length
+        };
+        self.registers.pc = pc.wrapping_add(length as u16);
+        Ok(instruction)
+    }
+
+    // Jumps to the highest priority pending interrupt's vector, returning
+    // whether there was one. Takes 5 M-cycles: two waiting, two pushing PC
+    // and one setting PC.
+    fn handle_interrupt(&mut self, mmu: &mut MMU) -> Result<bool, EmulatorError> {
+        let interrupt = match Interrupt::highest_priority(mmu.pending_interrupts()) {
+            Some(interrupt) => interrupt,
+            None => return Ok(false),
+        };
+        trace!("Handling interrupt {:?}", interrupt);
+        self.ime = false;
+        mmu.clear_interrupt(interrupt);
+        self.tick(mmu);
+        // The second wait is the one spent decrementing SP in call.
+        self.call(mmu, interrupt.vector())?;
+        self.tick(mmu);
+        Ok(true)
+    }
+
+    // Executes a decoded instruction, assuming PC already points past it and
+    // its bytes have been fetched.
+    pub fn execute(
+        &mut self,
+        mmu: &mut MMU,
+        instruction: &Instruction,
+    ) -> Result<(), EmulatorError> {
+        match instruction.operation {
+            Operation::Nop => (),
+            Operation::Stop => {
+                // DIV is reset either way. On the CGB a prepared speed switch
+                // happens instead of entering low power mode.
+                mmu.reset_div();
+                if mmu.switch_speed() {
+                    info!("Switched to double speed: {}", mmu.double_speed());
+                } else if !mmu.joypad_line_low() {
+                    info!("CPU stopping");
+                    self.stopped = true;
+                }
+            }
+            Operation::Halt => {
+                if !self.ime && mmu.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    info!("CPU halting");
+                    self.halted = true;
+                }
+            }
+            Operation::Di => {
+                self.ime = false;
+                self.ime_pending = false;
+            }
+            Operation::Ei => self.ime_pending = true,
+            Operation::Ld(dst, src) => {
+                let val = self.read_operand(mmu, src)?;
+                self.write_operand(mmu, dst, val)?;
+            }
+            Operation::Ld16(reg, val) => self.set_reg16(reg, val),
+            Operation::LdImmSp(addr) => {
+                let sp = self.registers.sp;
+                self.write(mmu, addr, sp as u8)?;
+                self.write(mmu, addr.wrapping_add(1), (sp >> 8) as u8)?;
+            }
+            Operation::LdSpHl => {
+                self.tick(mmu);
+                self.registers.sp = self.registers.hl();
+            }
+            Operation::LdHlSpOffset(offset) => {
+                self.tick(mmu);
+                let val =
+                    ::cpu::alu::add_sp_offset(self.registers.sp, offset, &mut self.registers.f);
+                self.registers.set_hl(val);
+            }
+            Operation::Push(reg) => {
+                let val = self.reg16(reg);
+                self.push(mmu, val)?;
+            }
+            Operation::Pop(reg) => {
+                let val = self.pop(mmu)?;
+                self.set_reg16(reg, val);
+            }
+            Operation::Add(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::add(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Adc(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::add_carry(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Sub(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::sub(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Sbc(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::sub_carry(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::And(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::and(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Xor(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::xor(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Or(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::or(&mut self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Cp(src) => {
+                let val = self.read_operand(mmu, src)?;
+                ::cpu::alu::cp(self.registers.a, Some(val), &mut self.registers.f);
+            }
+            Operation::Inc(operand) => {
+                self.modify_operand(mmu, operand, ::cpu::alu::inc)?;
+            }
+            Operation::Dec(operand) => {
+                self.modify_operand(mmu, operand, ::cpu::alu::dec)?;
+            }
+            // 16 bit INC and DEC don't update flags, so don't need the ALU.
+            Operation::Inc16(reg) => {
+                self.tick(mmu);
+                let val = self.reg16(reg).wrapping_add(1);
+                self.set_reg16(reg, val);
+            }
+            Operation::Dec16(reg) => {
+                self.tick(mmu);
+                let val = self.reg16(reg).wrapping_sub(1);
+                self.set_reg16(reg, val);
+            }
+            Operation::AddHl(reg) => {
+                self.tick(mmu);
+                let val = self.reg16(reg);
+                let mut hl = self.registers.hl();
+                ::cpu::alu::add16(&mut hl, val, &mut self.registers.f);
+                self.registers.set_hl(hl);
+            }
+            Operation::AddSp(offset) => {
+                self.tick(mmu);
+                self.tick(mmu);
+                self.registers.sp =
+                    ::cpu::alu::add_sp_offset(self.registers.sp, offset, &mut self.registers.f);
+            }
+            Operation::Rlca => ::cpu::alu::rlca(&mut self.registers.a, &mut self.registers.f),
+            Operation::Rrca => ::cpu::alu::rrca(&mut self.registers.a, &mut self.registers.f),
+            Operation::Rla => ::cpu::alu::rla(&mut self.registers.a, &mut self.registers.f),
+            Operation::Rra => ::cpu::alu::rra(&mut self.registers.a, &mut self.registers.f),
+            Operation::Daa => ::cpu::alu::daa(&mut self.registers.a, &mut self.registers.f),
+            Operation::Cpl => ::cpu::alu::cpl(&mut self.registers.a, &mut self.registers.f),
+            Operation::Scf => ::cpu::alu::scf(&mut self.registers.f),
+            Operation::Ccf => ::cpu::alu::ccf(&mut self.registers.f),
+            // Taken branches spend an extra M-cycle setting PC.
+            Operation::Jp(condition, addr) => {
+                if self.condition(condition) {
+                    self.tick(mmu);
+                    self.registers.pc = addr;
+                }
+            }
+            Operation::JpHl => self.registers.pc = self.registers.hl(),
+            Operation::Jr(condition, offset) => {
+                if self.condition(condition) {
+                    self.tick(mmu);
+                    self.jump_relative(offset);
+                }
+            }
+            Operation::Call(condition, addr) => {
+                if self.condition(condition) {
+                    self.call(mmu, addr)?;
+                }
+            }
+            Operation::Ret(condition) => {
+                // Checking the condition takes an M-cycle of its own.
+                if condition.is_some() {
+                    self.tick(mmu);
+                }
+                if self.condition(condition) {
+                    self.registers.pc = self.pop(mmu)?;
+                    self.tick(mmu);
+                }
+            }
+            Operation::Reti => {
+                // Unlike EI, there is no delay here.
+                self.ime = true;
+                self.registers.pc = self.pop(mmu)?;
+                self.tick(mmu);
+            }
+            Operation::Rst(vector) => self.call(mmu, vector as u16)?,
+            Operation::Rlc(operand) => self.modify_operand(mmu, operand, ::cpu::alu::rlc)?,
+            Operation::Rrc(operand) => self.modify_operand(mmu, operand, ::cpu::alu::rrc)?,
+            Operation::Rl(operand) => self.modify_operand(mmu, operand, ::cpu::alu::rl)?,
+            Operation::Rr(operand) => self.modify_operand(mmu, operand, ::cpu::alu::rr)?,
+            Operation::Sla(operand) => self.modify_operand(mmu, operand, ::cpu::alu::sla)?,
+            Operation::Sra(operand) => self.modify_operand(mmu, operand, ::cpu::alu::sra)?,
+            Operation::Swap(operand) => self.modify_operand(mmu, operand, ::cpu::alu::swap)?,
+            Operation::Srl(operand) => self.modify_operand(mmu, operand, ::cpu::alu::srl)?,
+            Operation::Bit(bit, operand) => {
+                let val = self.read_operand(mmu, operand)?;
+                ::cpu::alu::bit(val, bit, &mut self.registers.f);
+            }
+            Operation::Res(bit, operand) => {
+                let mut val = self.read_operand(mmu, operand)?;
+                ::cpu::alu::res(&mut val, bit);
+                self.write_operand(mmu, operand, val)?;
+            }
+            Operation::Set(bit, operand) => {
+                let mut val = self.read_operand(mmu, operand)?;
+                ::cpu::alu::set(&mut val, bit);
+                self.write_operand(mmu, operand, val)?;
+            }
+            Operation::Illegal(opcode) => {
+                let pc = self.registers.pc.wrapping_sub(instruction.length as u16);
+                match self.illegal_opcode_mode {
+                    IllegalOpcodeMode::Error => {
+                        return Err(EmulatorError::IllegalOpcode { pc, opcode })
+                    }
+                    IllegalOpcodeMode::Lock => {
+                        warn!("CPU locked up on opcode ${:02x} at ${:04x}", opcode, pc);
+                        self.locked = true;
+                    }
+                }
             }
-            // OR A,#
-            0xF6 => {
-                let byte = self.fetch_byte();
-                ::cpu::alu::or(&mut self.registers.a, Some(byte), &mut self.registers.f);
-                8
+        }
+        Ok(())
+    }
+
+    // Unconditional branches are always taken.
+    fn condition(&self, condition: Option<Condition>) -> bool {
+        match condition {
+            None => true,
+            Some(Condition::NZ) => !self.registers.z(),
+            Some(Condition::Z) => self.registers.z(),
+            Some(Condition::NC) => !self.registers.c(),
+            Some(Condition::C) => self.registers.c(),
+        }
+    }
+
+    fn reg8(&mut self, reg: Reg8) -> &mut u8 {
+        match reg {
+            Reg8::A => &mut self.registers.a,
+            Reg8::B => &mut self.registers.b,
+            Reg8::C => &mut self.registers.c,
+            Reg8::D => &mut self.registers.d,
+            Reg8::E => &mut self.registers.e,
+            Reg8::H => &mut self.registers.h,
+            Reg8::L => &mut self.registers.l,
+        }
+    }
+
+    fn reg16(&self, reg: Reg16) -> u16 {
+        match reg {
+            Reg16::BC => self.registers.bc(),
+            Reg16::DE => self.registers.de(),
+            Reg16::HL => self.registers.hl(),
+            Reg16::SP => self.registers.sp,
+            Reg16::AF => self.registers.af(),
+        }
+    }
+
+    fn set_reg16(&mut self, reg: Reg16, val: u16) {
+        match reg {
+            Reg16::BC => self.registers.set_bc(val),
+            Reg16::DE => self.registers.set_de(val),
+            Reg16::HL => self.registers.set_hl(val),
+            Reg16::SP => self.registers.sp = val,
+            Reg16::AF => self.registers.set_af(val),
+        }
+    }
+
+    // Memory address an operand refers to, if it's in memory. (HL+) and
+    // (HL-) update HL as a side effect.
+    fn operand_addr(&mut self, operand: Operand8) -> Option<u16> {
+        match operand {
+            Operand8::Reg(_) | Operand8::Imm(_) => None,
+            Operand8::Ind(reg) => Some(self.reg16(reg)),
+            Operand8::IndHLInc => {
+                let hl = self.registers.hl();
+                self.registers.set_hl(hl.wrapping_add(1));
+                Some(hl)
+            }
+            Operand8::IndHLDec => {
+                let hl = self.registers.hl();
+                self.registers.set_hl(hl.wrapping_sub(1));
+                Some(hl)
+            }
+            Operand8::IndImm(addr) => Some(addr),
+            Operand8::HighImm(offset) => Some(0xFF00 | offset as u16),
+            Operand8::HighC => Some(0xFF00 | self.registers.c as u16),
+        }
+    }
+
+    fn read_operand(&mut self, mmu: &mut MMU, operand: Operand8) -> Result<u8, EmulatorError> {
+        match operand {
+            Operand8::Reg(reg) => Ok(*self.reg8(reg)),
+            Operand8::Imm(val) => Ok(val),
+            _ => {
+                let addr = self.operand_addr(operand).unwrap();
+                self.read(mmu, addr)
             }
-            // CP A,#
-            0xFE => {
-                let byte = self.fetch_byte();
-                ::cpu::alu::cp(self.registers.a, Some(byte), &mut self.registers.f);
-                8
+        }
+    }
+
+    fn write_operand(
+        &mut self,
+        mmu: &mut MMU,
+        operand: Operand8,
+        val: u8,
+    ) -> Result<(), EmulatorError> {
+        match operand {
+            Operand8::Reg(reg) => {
+                *self.reg8(reg) = val;
+                Ok(())
+            }
+            Operand8::Imm(_) => unreachable!("Can't write to an immediate"),
+            _ => {
+                let addr = self.operand_addr(operand).unwrap();
+                self.write(mmu, addr, val)
             }
-            other => panic!("Instruction {} not implemented!", other),
         }
     }
+
+    // Read-modify-write through one of the ALU functions. Only used with
+    // registers and (HL), so the address doesn't change in between.
+    fn modify_operand(
+        &mut self,
+        mmu: &mut MMU,
+        operand: Operand8,
+        op: fn(&mut u8, &mut Flags),
+    ) -> Result<(), EmulatorError> {
+        let mut val = self.read_operand(mmu, operand)?;
+        op(&mut val, &mut self.registers.f);
+        self.write_operand(mmu, operand, val)
+    }
+
+    // Pushing spends an M-cycle decrementing SP before the two writes.
+    fn push(&mut self, mmu: &mut MMU, val: u16) -> Result<(), EmulatorError> {
+        self.tick(mmu);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(mmu, self.registers.sp, (val >> 8) as u8)?;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(mmu, self.registers.sp, val as u8)
+    }
+
+    fn pop(&mut self, mmu: &mut MMU) -> Result<u16, EmulatorError> {
+        let low = self.read(mmu, self.registers.sp)? as u16;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = self.read(mmu, self.registers.sp)? as u16;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        Ok((high << 8) | low)
+    }
+
+    fn call(&mut self, mmu: &mut MMU, addr: u16) -> Result<(), EmulatorError> {
+        let pc = self.registers.pc;
+        self.push(mmu, pc)?;
+        self.registers.pc = addr;
+        Ok(())
+    }
+
+    // The offset is a signed byte relative to the address after the JR.
+    fn jump_relative(&mut self, offset: i8) {
+        self.registers.pc = self.registers.pc.wrapping_add(offset as u16);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cartridge::Cartridge;
+    use joypad::Button;
+
+    // A CPU and the bus it runs on, as the model's boot ROM leaves them.
+    fn machine(model: Model) -> (CPU, MMU) {
+        // Just the 16KB of bank 0, so there's nothing at 0x4000.
+        let cartridge = Cartridge::from_bytes(vec![0; 0x4000]).unwrap();
+        (CPU::new(model, 0), MMU::new(model, cartridge))
+    }
 
     #[test]
     fn cpu_creates_properly() {
-        let cpu = CPU::new().unwrap();
+        let (cpu, _) = machine(Model::DMG);
         assert!(!cpu.halted);
     }
+
+    #[test]
+    fn cpu_starts_in_post_boot_state() {
+        let (cpu, mmu) = machine(Model::CGB);
+        assert_eq!(cpu.registers.pc, 0x0100);
+        assert_eq!(cpu.registers.a, 0x11);
+        assert_eq!(mmu.fetch(0xFF40).unwrap(), 0x91);
+        assert_eq!(mmu.fetch(0xFF0F).unwrap(), 0xE1);
+    }
+
+    // Loads a program into work RAM and points the CPU at it. The VBlank
+    // request left by the boot ROM is cleared so tests start quiet.
+    fn cpu_with_program(program: &[u8]) -> (CPU, MMU) {
+        let (mut cpu, mut mmu) = machine(Model::DMG);
+        mmu.set_mem_addr(0xFF0F, 0).unwrap();
+        for (i, byte) in program.iter().enumerate() {
+            mmu.set_mem_addr(0xC000 + i as u16, *byte).unwrap();
+        }
+        cpu.registers.pc = 0xC000;
+        (cpu, mmu)
+    }
+
+    #[test]
+    fn ld_u16_is_little_endian() {
+        // LD BC,0x1234
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x01, 0x34, 0x12]);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert_eq!(cpu.registers.bc(), 0x1234);
+    }
+
+    #[test]
+    fn jr_conditional_cycles_depend_on_branch() {
+        // XOR A; JR NZ,+2; JR Z,-4
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xAF, 0x20, 0x02, 0x28, 0xFC]);
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 8);
+        assert_eq!(cpu.registers.pc, 0xC003);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert_eq!(cpu.registers.pc, 0xC001);
+    }
+
+    #[test]
+    fn call_and_ret_use_the_stack() {
+        // CALL 0xC004; HALT; RET
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xCD, 0x04, 0xC0, 0x76, 0xC9]);
+        cpu.registers.sp = 0xD000;
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 24);
+        assert_eq!(cpu.registers.pc, 0xC004);
+        assert_eq!(cpu.registers.sp, 0xCFFE);
+        assert_eq!(mmu.fetch(0xCFFE).unwrap(), 0x03);
+        assert_eq!(mmu.fetch(0xCFFF).unwrap(), 0xC0);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 16);
+        assert_eq!(cpu.registers.pc, 0xC003);
+        assert_eq!(cpu.registers.sp, 0xD000);
+    }
+
+    #[test]
+    fn pop_af_clears_low_nibble_of_f() {
+        // LD BC,0x12FF; PUSH BC; POP AF
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x01, 0xFF, 0x12, 0xC5, 0xF1]);
+        cpu.registers.sp = 0xD000;
+        cpu.cycle(&mut mmu).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.registers.a, 0x12);
+        assert_eq!(cpu.registers.f.bits(), 0xF0);
+    }
+
+    #[test]
+    fn add_hl_sets_half_carry_from_bit_11() {
+        // ADD HL,BC
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x09]);
+        cpu.registers.set_hl(0x0FFF);
+        cpu.registers.set_bc(0x0001);
+        cpu.registers.f = Flags::from_bits(0b10000000);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 8);
+        assert_eq!(cpu.registers.hl(), 0x1000);
+        // Z is left alone
+        assert_eq!(cpu.registers.f.bits(), 0b10100000);
+    }
+
+    #[test]
+    fn ld_hl_sp_offset_uses_low_byte_for_flags() {
+        // LD HL,SP-1
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xF8, 0xFF]);
+        cpu.registers.sp = 0x0001;
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert_eq!(cpu.registers.hl(), 0x0000);
+        assert_eq!(cpu.registers.f.bits(), 0b00110000);
+    }
+
+    #[test]
+    fn ldh_reads_and_writes_high_ram() {
+        // LD A,0x42; LDH (0x80),A; XOR A; LDH A,(0x80)
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x3E, 0x42, 0xE0, 0x80, 0xAF, 0xF0, 0x80]);
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert_eq!(mmu.fetch(0xFF80).unwrap(), 0x42);
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert_eq!(cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn cb_swap_register() {
+        // SWAP B
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xCB, 0x30]);
+        cpu.registers.b = 0xF0;
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 8);
+        assert_eq!(cpu.registers.b, 0x0F);
+    }
+
+    #[test]
+    fn cb_hl_operand_timing() {
+        // SET 7,(HL); BIT 7,(HL); RES 7,(HL)
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xCB, 0xFE, 0xCB, 0x7E, 0xCB, 0xBE]);
+        cpu.registers.set_hl(0xC100);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 16);
+        assert_eq!(mmu.fetch(0xC100).unwrap(), 0x80);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert!(!cpu.registers.z());
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 16);
+        assert_eq!(mmu.fetch(0xC100).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn ei_is_delayed_by_one_instruction() {
+        // EI; NOP; NOP
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xFB, 0x00, 0x00]);
+        cpu.registers.sp = 0xD000;
+        mmu.set_mem_addr(0xFFFF, 0x04).unwrap();
+        mmu.request_interrupt(Interrupt::Timer);
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(!cpu.ime);
+        // The NOP after EI still runs before the interrupt is served.
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 4);
+        assert!(cpu.ime);
+        assert_eq!(cpu.registers.pc, 0xC002);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 20);
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert!(!cpu.ime);
+        assert_eq!(mmu.fetch(0xFF0F).unwrap() & 0x1F, 0);
+        assert_eq!(cpu.pop(&mut mmu).unwrap(), 0xC002);
+    }
+
+    #[test]
+    fn di_after_ei_cancels_it() {
+        // EI; DI; NOP
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xFB, 0xF3, 0x00]);
+        cpu.cycle(&mut mmu).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn interrupts_dispatch_in_priority_order() {
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x00]);
+        cpu.registers.sp = 0xD000;
+        cpu.ime = true;
+        mmu.set_mem_addr(0xFFFF, 0x1F).unwrap();
+        mmu.set_mem_addr(0xFF0F, 0b10010).unwrap();
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 20);
+        assert_eq!(cpu.registers.pc, 0x48);
+        assert_eq!(mmu.fetch(0xFF0F).unwrap(), 0xE0 | 0b10000);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_immediately() {
+        // RETI
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xD9]);
+        cpu.registers.sp = 0xD000;
+        cpu.push(&mut mmu, 0xC100).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(cpu.ime);
+        assert_eq!(cpu.registers.pc, 0xC100);
+    }
+
+    #[test]
+    fn halt_wakes_on_pending_interrupt() {
+        // HALT; NOP
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x76, 0x00]);
+        cpu.registers.sp = 0xD000;
+        cpu.ime = true;
+        mmu.set_mem_addr(0xFFFF, 0x01).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(cpu.halted);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 4);
+        assert!(cpu.halted);
+        mmu.request_interrupt(Interrupt::VBlank);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 20);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.pc, 0x40);
+        assert_eq!(cpu.pop(&mut mmu).unwrap(), 0xC001);
+    }
+
+    #[test]
+    fn halt_without_ime_resumes_without_dispatch() {
+        // HALT; INC A
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x76, 0x3C]);
+        mmu.set_mem_addr(0xFFFF, 0x01).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(cpu.halted);
+        mmu.request_interrupt(Interrupt::VBlank);
+        let a = cpu.registers.a;
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.a, a.wrapping_add(1));
+        assert_eq!(cpu.registers.pc, 0xC002);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        // HALT; INC A
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x76, 0x3C]);
+        mmu.set_mem_addr(0xFFFF, 0x01).unwrap();
+        mmu.request_interrupt(Interrupt::VBlank);
+        let a = cpu.registers.a;
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(!cpu.halted);
+        cpu.cycle(&mut mmu).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.registers.a, a.wrapping_add(2));
+        assert_eq!(cpu.registers.pc, 0xC002);
+    }
+
+    #[test]
+    fn rst_jumps_to_vector() {
+        // RST 38h
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xFF]);
+        cpu.registers.sp = 0xD000;
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 16);
+        assert_eq!(cpu.registers.pc, 0x0038);
+        assert_eq!(cpu.registers.sp, 0xCFFE);
+    }
+
+    #[test]
+    fn illegal_opcode_is_an_error() {
+        // NOP; illegal 0xD3
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x00, 0xD3]);
+        cpu.cycle(&mut mmu).unwrap();
+        match cpu.cycle(&mut mmu) {
+            Err(EmulatorError::IllegalOpcode { pc, opcode }) => {
+                assert_eq!(pc, 0xC001);
+                assert_eq!(opcode, 0xD3);
+            }
+            other => panic!("Expected an illegal opcode, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reading_past_the_end_of_the_rom_is_an_error() {
+        let (mut cpu, mut mmu) = machine(Model::DMG);
+        cpu.registers.pc = 0x4000;
+        assert!(matches!(
+            cpu.cycle(&mut mmu),
+            Err(EmulatorError::OutOfBoundsAccess { addr: 0x4000, .. })
+        ));
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up_in_lock_mode() {
+        // Illegal 0xDD; INC A
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xDD, 0x3C]);
+        cpu.registers.sp = 0xD000;
+        cpu.illegal_opcode_mode = IllegalOpcodeMode::Lock;
+        let a = cpu.registers.a;
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 4);
+        assert!(cpu.locked);
+        // Interrupts don't get it out either.
+        cpu.ime = true;
+        mmu.set_mem_addr(0xFFFF, 0x01).unwrap();
+        mmu.request_interrupt(Interrupt::VBlank);
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 4);
+        assert_eq!(cpu.registers.a, a);
+        assert_eq!(cpu.registers.pc, 0xC001);
+    }
+
+    #[test]
+    fn stop_waits_for_a_joypad_line() {
+        // STOP; INC A
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x10, 0x00, 0x3C]);
+        let a = cpu.registers.a;
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(cpu.stopped);
+        assert_eq!(mmu.fetch(0xFF04).unwrap(), 0);
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.registers.a, a);
+        // Buttons are selected after boot, so pressing one wakes the CPU.
+        mmu.press_button(Button::Start);
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.registers.a, a.wrapping_add(1));
+    }
+
+    #[test]
+    fn stop_switches_speed_on_cgb() {
+        // STOP
+        let (mut cpu, mut mmu) = machine(Model::CGB);
+        mmu.set_mem_addr(0xC000, 0x10).unwrap();
+        cpu.registers.pc = 0xC000;
+        mmu.set_mem_addr(0xFF4D, 0x01).unwrap();
+        assert_eq!(mmu.fetch(0xFF4D).unwrap(), 0x7F);
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(!cpu.stopped);
+        assert!(mmu.double_speed());
+        assert_eq!(mmu.fetch(0xFF4D).unwrap(), 0xFE);
+    }
+
+    #[test]
+    fn key1_is_not_there_on_dmg() {
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x10, 0x00]);
+        mmu.set_mem_addr(0xFF4D, 0x01).unwrap();
+        assert_eq!(mmu.fetch(0xFF4D).unwrap(), 0xFF);
+        cpu.cycle(&mut mmu).unwrap();
+        assert!(cpu.stopped);
+        assert!(!mmu.double_speed());
+    }
+
+    #[test]
+    fn timer_interrupt_is_requested_on_overflow() {
+        // NOP
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x00]);
+        mmu.set_mem_addr(0xFF05, 0xFF).unwrap();
+        // Enabled, every 16 cycles
+        mmu.set_mem_addr(0xFF07, 0x05).unwrap();
+        mmu.reset_div();
+        // Four NOPs are 16 cycles.
+        for _ in 0..4 {
+            cpu.registers.pc = 0xC000;
+            cpu.cycle(&mut mmu).unwrap();
+        }
+        assert_eq!(mmu.fetch(0xFF0F).unwrap() & 0x1F, 0x04);
+    }
+
+    #[test]
+    fn reads_see_the_timer_at_their_own_m_cycle() {
+        // LD A,(0xFF05), with TIMA going up every 4 M-cycles
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xFA, 0x05, 0xFF]);
+        mmu.set_mem_addr(0xFF07, 0x05).unwrap();
+        mmu.reset_div();
+        // The read is in the fourth M-cycle, after TIMA has gone up.
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 16);
+        assert_eq!(cpu.registers.a, 1);
+
+        // LDH A,(0x05) reads in its third M-cycle, before it does.
+        let (mut cpu, mut mmu) = cpu_with_program(&[0xF0, 0x05]);
+        mmu.set_mem_addr(0xFF07, 0x05).unwrap();
+        mmu.reset_div();
+        assert_eq!(cpu.cycle(&mut mmu).unwrap(), 12);
+        assert_eq!(cpu.registers.a, 0);
+    }
+
+    #[test]
+    fn oam_dma_blocks_everything_below_high_ram() {
+        // From HRAM: LDH (0x46),A; LD A,(HL); JR -2
+        let (mut cpu, mut mmu) = machine(Model::DMG);
+        for (i, byte) in [0xE0, 0x46, 0x7E, 0x18, 0xFE].iter().enumerate() {
+            mmu.set_mem_addr(0xFF80 + i as u16, *byte).unwrap();
+        }
+        for i in 0..0xA0 {
+            mmu.set_mem_addr(0xC100 + i, i as u8).unwrap();
+        }
+        // Turn the LCD off so the PPU doesn't lock OAM as well.
+        mmu.set_mem_addr(0xFF40, 0x11).unwrap();
+        mmu.set_mem_addr(0xFF0F, 0).unwrap();
+        cpu.registers.pc = 0xFF80;
+        cpu.registers.a = 0xC1;
+        cpu.registers.set_hl(0xC100);
+
+        cpu.cycle(&mut mmu).unwrap();
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.registers.a, 0xFF);
+        for _ in 0..60 {
+            cpu.cycle(&mut mmu).unwrap();
+        }
+        assert_eq!(mmu.fetch(0xC100).unwrap(), 0x00);
+        assert_eq!(mmu.fetch(0xFE05).unwrap(), 0x05);
+        assert_eq!(mmu.fetch(0xFE9F).unwrap(), 0x9F);
+    }
+
+    #[test]
+    fn vram_is_locked_while_the_ppu_draws() {
+        // LD A,(HL)
+        let (mut cpu, mut mmu) = cpu_with_program(&[0x7E]);
+        mmu.set_mem_addr(0xFF40, 0x11).unwrap();
+        mmu.set_mem_addr(0x8000, 0x42).unwrap();
+        mmu.set_mem_addr(0xFF40, 0x91).unwrap();
+        cpu.registers.set_hl(0x8000);
+        // Move into mode 3, 80 dots into the line.
+        for _ in 0..20 {
+            mmu.tick();
+        }
+        cpu.cycle(&mut mmu).unwrap();
+        assert_eq!(cpu.registers.a, 0xFF);
+    }
+
+    // Runs an instruction with every flag clear, then every flag set, which
+    // covers both outcomes of each condition.
+    fn measure_both_ways(program: &[u8]) -> (Instruction, Vec<u8>) {
+        let mut measured = vec![];
+        let mut instruction = None;
+        for &flags in &[0x00, 0xF0] {
+            let (mut cpu, mut mmu) = cpu_with_program(program);
+            cpu.registers.sp = 0xD000;
+            cpu.registers.set_bc(0xC800);
+            cpu.registers.set_de(0xC800);
+            cpu.registers.set_hl(0xC800);
+            cpu.registers.f = Flags::from_bits(flags);
+            instruction = Some(cpu.decode_at(&mmu, 0xC000).unwrap());
+            measured.push(cpu.cycle(&mut mmu).unwrap());
+        }
+        measured.sort();
+        (instruction.unwrap(), measured)
+    }
+
+    #[test]
+    fn executed_cycles_match_instruction_timing() {
+        for opcode in 0..=0xFFu8 {
+            // Immediates point into work RAM so nothing touches the ROM.
+            let program = [opcode, 0x00, 0xC8];
+            let operation = Instruction::decode_bytes(&program).operation;
+            if opcode == 0xCB || matches!(operation, Operation::Illegal(_)) {
+                continue;
+            }
+            let (instruction, measured) = measure_both_ways(&program);
+            let mut expected = vec![instruction.cycles, instruction.branch_cycles];
+            expected.sort();
+            assert_eq!(measured, expected, "{} ({:02x})", instruction, opcode);
+        }
+
+        for cb_opcode in 0..=0xFFu8 {
+            let (instruction, measured) = measure_both_ways(&[0xCB, cb_opcode]);
+            assert_eq!(
+                measured,
+                vec![instruction.cycles; 2],
+                "{} (cb {:02x})",
+                instruction,
+                cb_opcode
+            );
+        }
+    }
 }
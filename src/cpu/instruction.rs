@@ -0,0 +1,816 @@
+// Decoding of SM83 opcodes into instructions, separate from executing them so
+// the same decoder can be used for disassembly, debugging and tracing.
+//
+// Opcodes are laid out in a regular grid, so most of them are decoded from
+// their bit fields rather than listed one by one:
+//   x = bits 7-6, y = bits 5-3, z = bits 2-0, p = bits 5-4, q = bit 3
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg8 {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg16 {
+    BC,
+    DE,
+    HL,
+    SP,
+    AF,
+}
+
+// Somewhere an 8 bit value can be read from or written to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand8 {
+    Reg(Reg8),
+    Imm(u8),
+    // (BC), (DE) or (HL)
+    Ind(Reg16),
+    // (HL+) and (HL-)
+    IndHLInc,
+    IndHLDec,
+    // (u16)
+    IndImm(u16),
+    // (FF00+u8) and (FF00+C)
+    HighImm(u8),
+    HighC,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    // Destination, source
+    Ld(Operand8, Operand8),
+    Ld16(Reg16, u16),
+    // LD (u16),SP
+    LdImmSp(u16),
+    LdSpHl,
+    // LD HL,SP+i8
+    LdHlSpOffset(i8),
+    Push(Reg16),
+    Pop(Reg16),
+    Add(Operand8),
+    Adc(Operand8),
+    Sub(Operand8),
+    Sbc(Operand8),
+    And(Operand8),
+    Xor(Operand8),
+    Or(Operand8),
+    Cp(Operand8),
+    Inc(Operand8),
+    Dec(Operand8),
+    Inc16(Reg16),
+    Dec16(Reg16),
+    AddHl(Reg16),
+    AddSp(i8),
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jp(Option<Condition>, u16),
+    JpHl,
+    // Offset is relative to the address after the instruction.
+    Jr(Option<Condition>, i8),
+    Call(Option<Condition>, u16),
+    Ret(Option<Condition>),
+    Reti,
+    Rst(u8),
+    // CB prefixed
+    Rlc(Operand8),
+    Rrc(Operand8),
+    Rl(Operand8),
+    Rr(Operand8),
+    Sla(Operand8),
+    Sra(Operand8),
+    Swap(Operand8),
+    Srl(Operand8),
+    Bit(u8, Operand8),
+    Res(u8, Operand8),
+    Set(u8, Operand8),
+    // One of the 11 opcodes that don't exist.
+    Illegal(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub operation: Operation,
+    // Length in bytes, including the CB prefix and any immediates.
+    pub length: u8,
+    // Cycles taken, or for conditional branches the cycles when not taken.
+    pub cycles: u8,
+    // Cycles taken when a conditional branch is taken. Same as cycles for
+    // everything else.
+    pub branch_cycles: u8,
+}
+
+// 8 bit operands in the order they're encoded: B, C, D, E, H, L, (HL), A
+fn r(index: u8) -> Operand8 {
+    match index & 7 {
+        0 => Operand8::Reg(Reg8::B),
+        1 => Operand8::Reg(Reg8::C),
+        2 => Operand8::Reg(Reg8::D),
+        3 => Operand8::Reg(Reg8::E),
+        4 => Operand8::Reg(Reg8::H),
+        5 => Operand8::Reg(Reg8::L),
+        6 => Operand8::Ind(Reg16::HL),
+        _ => Operand8::Reg(Reg8::A),
+    }
+}
+
+// 16 bit registers for loads and arithmetic.
+fn rp(index: u8) -> Reg16 {
+    match index & 3 {
+        0 => Reg16::BC,
+        1 => Reg16::DE,
+        2 => Reg16::HL,
+        _ => Reg16::SP,
+    }
+}
+
+// 16 bit registers for PUSH and POP, where AF replaces SP.
+fn rp2(index: u8) -> Reg16 {
+    match index & 3 {
+        0 => Reg16::BC,
+        1 => Reg16::DE,
+        2 => Reg16::HL,
+        _ => Reg16::AF,
+    }
+}
+
+fn cc(index: u8) -> Condition {
+    match index & 3 {
+        0 => Condition::NZ,
+        1 => Condition::Z,
+        2 => Condition::NC,
+        _ => Condition::C,
+    }
+}
+
+fn alu(index: u8, operand: Operand8) -> Operation {
+    match index & 7 {
+        0 => Operation::Add(operand),
+        1 => Operation::Adc(operand),
+        2 => Operation::Sub(operand),
+        3 => Operation::Sbc(operand),
+        4 => Operation::And(operand),
+        5 => Operation::Xor(operand),
+        6 => Operation::Or(operand),
+        _ => Operation::Cp(operand),
+    }
+}
+
+// Extra cycles for reading or writing an 8 bit operand in memory.
+fn access_cycles(operand: Operand8) -> u8 {
+    match operand {
+        Operand8::Reg(_) => 0,
+        _ => 4,
+    }
+}
+
+// Reads bytes from wherever instructions are being decoded from, keeping
+// track of how many have been read.
+struct Reader<F: FnMut(u16) -> u8> {
+    addr: u16,
+    length: u8,
+    read: F,
+}
+
+impl<F: FnMut(u16) -> u8> Reader<F> {
+    fn byte(&mut self) -> u8 {
+        let byte = (self.read)(self.addr.wrapping_add(self.length as u16));
+        self.length += 1;
+        byte
+    }
+
+    fn word(&mut self) -> u16 {
+        let low = self.byte() as u16;
+        let high = self.byte() as u16;
+        (high << 8) | low
+    }
+}
+
+impl Instruction {
+    // Decodes the instruction at addr, reading bytes with the given function.
+    pub fn decode<F: FnMut(u16) -> u8>(addr: u16, read: F) -> Instruction {
+        let mut reader = Reader {
+            addr,
+            length: 0,
+            read,
+        };
+        let opcode = reader.byte();
+        let (operation, cycles, branch_cycles) = if opcode == 0xCB {
+            let cb_opcode = reader.byte();
+            let (operation, cycles) = Self::decode_cb(cb_opcode);
+            (operation, cycles, cycles)
+        } else {
+            Self::decode_unprefixed(opcode, &mut reader)
+        };
+        Instruction {
+            operation,
+            length: reader.length,
+            cycles,
+            branch_cycles,
+        }
+    }
+
+    // Decodes an instruction from the start of a byte slice. Bytes past the
+    // end of the slice read as 0.
+    pub fn decode_bytes(bytes: &[u8]) -> Instruction {
+        Self::decode(0, |addr| bytes.get(addr as usize).cloned().unwrap_or(0))
+    }
+
+    fn decode_unprefixed<F: FnMut(u16) -> u8>(
+        opcode: u8,
+        reader: &mut Reader<F>,
+    ) -> (Operation, u8, u8) {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let z = opcode & 7;
+        let p = y >> 1;
+        let q = y & 1;
+
+        // Most instructions take the same time no matter what, so only
+        // branches set branch cycles separately.
+        let same = |operation, cycles| (operation, cycles, cycles);
+
+        match (x, z) {
+            (0, 0) => match y {
+                0 => same(Operation::Nop, 4),
+                1 => same(Operation::LdImmSp(reader.word()), 20),
+                // STOP is followed by a byte that's ignored (always 0x00).
+                2 => {
+                    reader.byte();
+                    same(Operation::Stop, 4)
+                }
+                3 => same(Operation::Jr(None, reader.byte() as i8), 12),
+                _ => (Operation::Jr(Some(cc(y - 4)), reader.byte() as i8), 8, 12),
+            },
+            (0, 1) => {
+                if q == 0 {
+                    same(Operation::Ld16(rp(p), reader.word()), 12)
+                } else {
+                    same(Operation::AddHl(rp(p)), 8)
+                }
+            }
+            (0, 2) => {
+                let mem = match p {
+                    0 => Operand8::Ind(Reg16::BC),
+                    1 => Operand8::Ind(Reg16::DE),
+                    2 => Operand8::IndHLInc,
+                    _ => Operand8::IndHLDec,
+                };
+                let a = Operand8::Reg(Reg8::A);
+                if q == 0 {
+                    same(Operation::Ld(mem, a), 8)
+                } else {
+                    same(Operation::Ld(a, mem), 8)
+                }
+            }
+            (0, 3) => {
+                if q == 0 {
+                    same(Operation::Inc16(rp(p)), 8)
+                } else {
+                    same(Operation::Dec16(rp(p)), 8)
+                }
+            }
+            // Memory operands are read and written back.
+            (0, 4) => same(Operation::Inc(r(y)), 4 + 2 * access_cycles(r(y))),
+            (0, 5) => same(Operation::Dec(r(y)), 4 + 2 * access_cycles(r(y))),
+            (0, 6) => {
+                let imm = Operand8::Imm(reader.byte());
+                same(Operation::Ld(r(y), imm), 8 + access_cycles(r(y)))
+            }
+            (0, _) => {
+                let operation = match y {
+                    0 => Operation::Rlca,
+                    1 => Operation::Rrca,
+                    2 => Operation::Rla,
+                    3 => Operation::Rra,
+                    4 => Operation::Daa,
+                    5 => Operation::Cpl,
+                    6 => Operation::Scf,
+                    _ => Operation::Ccf,
+                };
+                same(operation, 4)
+            }
+            // LD (HL),(HL) is where HALT lives.
+            (1, 6) if y == 6 => same(Operation::Halt, 4),
+            (1, _) => same(
+                Operation::Ld(r(y), r(z)),
+                4 + access_cycles(r(y)) + access_cycles(r(z)),
+            ),
+            (2, _) => same(alu(y, r(z)), 4 + access_cycles(r(z))),
+            (_, 0) => match y {
+                0..=3 => (Operation::Ret(Some(cc(y))), 8, 20),
+                4 => {
+                    let imm = Operand8::HighImm(reader.byte());
+                    same(Operation::Ld(imm, Operand8::Reg(Reg8::A)), 12)
+                }
+                5 => same(Operation::AddSp(reader.byte() as i8), 16),
+                6 => {
+                    let imm = Operand8::HighImm(reader.byte());
+                    same(Operation::Ld(Operand8::Reg(Reg8::A), imm), 12)
+                }
+                _ => same(Operation::LdHlSpOffset(reader.byte() as i8), 12),
+            },
+            (_, 1) => {
+                if q == 0 {
+                    same(Operation::Pop(rp2(p)), 12)
+                } else {
+                    match p {
+                        0 => same(Operation::Ret(None), 16),
+                        1 => same(Operation::Reti, 16),
+                        2 => same(Operation::JpHl, 4),
+                        _ => same(Operation::LdSpHl, 8),
+                    }
+                }
+            }
+            (_, 2) => match y {
+                0..=3 => (Operation::Jp(Some(cc(y)), reader.word()), 12, 16),
+                4 => same(Operation::Ld(Operand8::HighC, Operand8::Reg(Reg8::A)), 8),
+                5 => {
+                    let imm = Operand8::IndImm(reader.word());
+                    same(Operation::Ld(imm, Operand8::Reg(Reg8::A)), 16)
+                }
+                6 => same(Operation::Ld(Operand8::Reg(Reg8::A), Operand8::HighC), 8),
+                _ => {
+                    let imm = Operand8::IndImm(reader.word());
+                    same(Operation::Ld(Operand8::Reg(Reg8::A), imm), 16)
+                }
+            },
+            (_, 3) => match y {
+                0 => same(Operation::Jp(None, reader.word()), 16),
+                6 => same(Operation::Di, 4),
+                7 => same(Operation::Ei, 4),
+                // 1 is the CB prefix, which is handled before getting here.
+                _ => same(Operation::Illegal(opcode), 4),
+            },
+            (_, 4) => match y {
+                0..=3 => (Operation::Call(Some(cc(y)), reader.word()), 12, 24),
+                _ => same(Operation::Illegal(opcode), 4),
+            },
+            (_, 5) => {
+                if q == 0 {
+                    same(Operation::Push(rp2(p)), 16)
+                } else if p == 0 {
+                    same(Operation::Call(None, reader.word()), 24)
+                } else {
+                    same(Operation::Illegal(opcode), 4)
+                }
+            }
+            (_, 6) => same(alu(y, Operand8::Imm(reader.byte())), 8),
+            (_, _) => same(Operation::Rst(y * 8), 16),
+        }
+    }
+
+    // Cycle counts include fetching the prefix.
+    fn decode_cb(opcode: u8) -> (Operation, u8) {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let operand = r(opcode);
+        let operation = match x {
+            0 => match y {
+                0 => Operation::Rlc(operand),
+                1 => Operation::Rrc(operand),
+                2 => Operation::Rl(operand),
+                3 => Operation::Rr(operand),
+                4 => Operation::Sla(operand),
+                5 => Operation::Sra(operand),
+                6 => Operation::Swap(operand),
+                _ => Operation::Srl(operand),
+            },
+            // BIT only reads, so it never writes back and is faster on (HL).
+            1 => return (Operation::Bit(y, operand), 8 + access_cycles(operand)),
+            2 => Operation::Res(y, operand),
+            _ => Operation::Set(y, operand),
+        };
+        (operation, 8 + 2 * access_cycles(operand))
+    }
+}
+
+// Instructions are displayed in rgbds syntax. Relative jumps don't know
+// where they are, so their targets are written relative to the current
+// address (@), which rgbasm also understands.
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Reg8::A => "a",
+            Reg8::B => "b",
+            Reg8::C => "c",
+            Reg8::D => "d",
+            Reg8::E => "e",
+            Reg8::H => "h",
+            Reg8::L => "l",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Reg16::BC => "bc",
+            Reg16::DE => "de",
+            Reg16::HL => "hl",
+            Reg16::SP => "sp",
+            Reg16::AF => "af",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Operand8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand8::Reg(reg) => write!(f, "{}", reg),
+            Operand8::Imm(val) => write!(f, "{}", val),
+            Operand8::Ind(reg) => write!(f, "[{}]", reg),
+            Operand8::IndHLInc => write!(f, "[hl+]"),
+            Operand8::IndHLDec => write!(f, "[hl-]"),
+            Operand8::IndImm(addr) => write!(f, "[${:04x}]", addr),
+            Operand8::HighImm(offset) => write!(f, "[${:04x}]", 0xFF00 | *offset as u16),
+            Operand8::HighC => write!(f, "[c]"),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Condition::NZ => "nz",
+            Condition::Z => "z",
+            Condition::NC => "nc",
+            Condition::C => "c",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Signed offsets are written as +n or -n.
+fn signed(offset: i8) -> String {
+    if offset < 0 {
+        format!("-{}", -(offset as i16))
+    } else {
+        format!("+{}", offset)
+    }
+}
+
+impl Operation {
+    // The address a jump or call goes to, given the address of the
+    // instruction. JP HL, RET and RST aren't included.
+    pub fn branch_target(&self, addr: u16, length: u8) -> Option<u16> {
+        match *self {
+            Operation::Jp(_, target) | Operation::Call(_, target) => Some(target),
+            Operation::Jr(_, offset) => {
+                Some(addr.wrapping_add(length as u16).wrapping_add(offset as u16))
+            }
+            _ => None,
+        }
+    }
+
+    // The instruction with the operand of a jump, call or relative jump
+    // replaced by the given text, e.g. a label.
+    pub fn with_target(&self, target: &str) -> String {
+        let (mnemonic, condition) = match *self {
+            Operation::Jp(condition, _) => ("jp", condition),
+            Operation::Jr(condition, _) => ("jr", condition),
+            Operation::Call(condition, _) => ("call", condition),
+            _ => return format!("{}", self),
+        };
+        match condition {
+            Some(condition) => format!("{} {}, {}", mnemonic, condition, target),
+            None => format!("{} {}", mnemonic, target),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operation::Nop => write!(f, "nop"),
+            Operation::Stop => write!(f, "stop"),
+            Operation::Halt => write!(f, "halt"),
+            Operation::Di => write!(f, "di"),
+            Operation::Ei => write!(f, "ei"),
+            // High memory loads use ldh so rgbasm picks the short encoding.
+            Operation::Ld(dst, src @ Operand8::HighImm(_))
+            | Operation::Ld(dst, src @ Operand8::HighC) => write!(f, "ldh {}, {}", dst, src),
+            Operation::Ld(dst @ Operand8::HighImm(_), src)
+            | Operation::Ld(dst @ Operand8::HighC, src) => write!(f, "ldh {}, {}", dst, src),
+            Operation::Ld(dst, src) => write!(f, "ld {}, {}", dst, src),
+            Operation::Ld16(reg, val) => write!(f, "ld {}, ${:04x}", reg, val),
+            Operation::LdImmSp(addr) => write!(f, "ld [${:04x}], sp", addr),
+            Operation::LdSpHl => write!(f, "ld sp, hl"),
+            Operation::LdHlSpOffset(offset) => write!(f, "ld hl, sp{}", signed(offset)),
+            Operation::Push(reg) => write!(f, "push {}", reg),
+            Operation::Pop(reg) => write!(f, "pop {}", reg),
+            Operation::Add(src) => write!(f, "add a, {}", src),
+            Operation::Adc(src) => write!(f, "adc a, {}", src),
+            Operation::Sub(src) => write!(f, "sub a, {}", src),
+            Operation::Sbc(src) => write!(f, "sbc a, {}", src),
+            Operation::And(src) => write!(f, "and a, {}", src),
+            Operation::Xor(src) => write!(f, "xor a, {}", src),
+            Operation::Or(src) => write!(f, "or a, {}", src),
+            Operation::Cp(src) => write!(f, "cp a, {}", src),
+            Operation::Inc(operand) => write!(f, "inc {}", operand),
+            Operation::Dec(operand) => write!(f, "dec {}", operand),
+            Operation::Inc16(reg) => write!(f, "inc {}", reg),
+            Operation::Dec16(reg) => write!(f, "dec {}", reg),
+            Operation::AddHl(reg) => write!(f, "add hl, {}", reg),
+            Operation::AddSp(offset) => write!(f, "add sp, {}", offset),
+            Operation::Rlca => write!(f, "rlca"),
+            Operation::Rrca => write!(f, "rrca"),
+            Operation::Rla => write!(f, "rla"),
+            Operation::Rra => write!(f, "rra"),
+            Operation::Daa => write!(f, "daa"),
+            Operation::Cpl => write!(f, "cpl"),
+            Operation::Scf => write!(f, "scf"),
+            Operation::Ccf => write!(f, "ccf"),
+            Operation::Jp(_, addr) | Operation::Call(_, addr) => {
+                write!(f, "{}", self.with_target(&format!("${:04x}", addr)))
+            }
+            Operation::JpHl => write!(f, "jp hl"),
+            // @ is the address of the jr itself, which is 2 bytes long.
+            Operation::Jr(_, offset) => {
+                let target = format!("@{}", signed(offset.wrapping_add(2)));
+                write!(f, "{}", self.with_target(&target))
+            }
+            Operation::Ret(Some(condition)) => write!(f, "ret {}", condition),
+            Operation::Ret(None) => write!(f, "ret"),
+            Operation::Reti => write!(f, "reti"),
+            Operation::Rst(vector) => write!(f, "rst ${:02x}", vector),
+            Operation::Rlc(operand) => write!(f, "rlc {}", operand),
+            Operation::Rrc(operand) => write!(f, "rrc {}", operand),
+            Operation::Rl(operand) => write!(f, "rl {}", operand),
+            Operation::Rr(operand) => write!(f, "rr {}", operand),
+            Operation::Sla(operand) => write!(f, "sla {}", operand),
+            Operation::Sra(operand) => write!(f, "sra {}", operand),
+            Operation::Swap(operand) => write!(f, "swap {}", operand),
+            Operation::Srl(operand) => write!(f, "srl {}", operand),
+            Operation::Bit(bit, operand) => write!(f, "bit {}, {}", bit, operand),
+            Operation::Res(bit, operand) => write!(f, "res {}, {}", bit, operand),
+            Operation::Set(bit, operand) => write!(f, "set {}, {}", bit, operand),
+            // Illegal opcodes can't be assembled, so write the raw byte.
+            Operation::Illegal(opcode) => write!(f, "db ${:02x}", opcode),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_register_load() {
+        // LD B,C
+        let instruction = Instruction::decode_bytes(&[0x41]);
+        assert_eq!(
+            instruction.operation,
+            Operation::Ld(Operand8::Reg(Reg8::B), Operand8::Reg(Reg8::C))
+        );
+        assert_eq!(instruction.length, 1);
+        assert_eq!(instruction.cycles, 4);
+    }
+
+    #[test]
+    fn decode_immediates_are_little_endian() {
+        // LD BC,0x1234
+        let instruction = Instruction::decode_bytes(&[0x01, 0x34, 0x12]);
+        assert_eq!(instruction.operation, Operation::Ld16(Reg16::BC, 0x1234));
+        assert_eq!(instruction.length, 3);
+        assert_eq!(instruction.cycles, 12);
+    }
+
+    #[test]
+    fn decode_conditional_branch_cycles() {
+        // JR NZ,-2
+        let instruction = Instruction::decode_bytes(&[0x20, 0xFE]);
+        assert_eq!(
+            instruction.operation,
+            Operation::Jr(Some(Condition::NZ), -2)
+        );
+        assert_eq!(instruction.cycles, 8);
+        assert_eq!(instruction.branch_cycles, 12);
+    }
+
+    #[test]
+    fn decode_halt_instead_of_hl_to_hl() {
+        let instruction = Instruction::decode_bytes(&[0x76]);
+        assert_eq!(instruction.operation, Operation::Halt);
+    }
+
+    #[test]
+    fn decode_cb_prefixed() {
+        // BIT 7,(HL)
+        let instruction = Instruction::decode_bytes(&[0xCB, 0x7E]);
+        assert_eq!(
+            instruction.operation,
+            Operation::Bit(7, Operand8::Ind(Reg16::HL))
+        );
+        assert_eq!(instruction.length, 2);
+        assert_eq!(instruction.cycles, 12);
+
+        // SET 0,(HL)
+        let instruction = Instruction::decode_bytes(&[0xCB, 0xC6]);
+        assert_eq!(instruction.cycles, 16);
+    }
+
+    #[test]
+    fn decode_illegal_opcodes() {
+        for opcode in [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ]
+        .iter()
+        {
+            let instruction = Instruction::decode_bytes(&[*opcode]);
+            assert_eq!(instruction.operation, Operation::Illegal(*opcode));
+            assert_eq!(instruction.length, 1);
+        }
+    }
+
+    fn asm(bytes: &[u8]) -> String {
+        format!("{}", Instruction::decode_bytes(bytes))
+    }
+
+    #[test]
+    fn display_uses_rgbds_syntax() {
+        assert_eq!(asm(&[0x3E, 0x0A]), "ld a, 10");
+        assert_eq!(asm(&[0x80]), "add a, b");
+        assert_eq!(asm(&[0x22]), "ld [hl+], a");
+        assert_eq!(asm(&[0x36, 0x05]), "ld [hl], 5");
+        assert_eq!(asm(&[0xEA, 0x00, 0xC0]), "ld [$c000], a");
+        assert_eq!(asm(&[0xE0, 0x80]), "ldh [$ff80], a");
+        assert_eq!(asm(&[0xF2]), "ldh a, [c]");
+        assert_eq!(asm(&[0xF8, 0xFE]), "ld hl, sp-2");
+        assert_eq!(asm(&[0xE8, 0x02]), "add sp, 2");
+        assert_eq!(asm(&[0xC2, 0x50, 0x01]), "jp nz, $0150");
+        assert_eq!(asm(&[0xCD, 0x00, 0x40]), "call $4000");
+        assert_eq!(asm(&[0xF5]), "push af");
+        assert_eq!(asm(&[0xFF]), "rst $38");
+        assert_eq!(asm(&[0xCB, 0x7E]), "bit 7, [hl]");
+        assert_eq!(asm(&[0xD3]), "db $d3");
+    }
+
+    #[test]
+    fn display_relative_jumps_from_current_address() {
+        assert_eq!(asm(&[0x18, 0xFE]), "jr @+0");
+        assert_eq!(asm(&[0x20, 0x05]), "jr nz, @+7");
+        assert_eq!(asm(&[0x38, 0xF0]), "jr c, @-14");
+    }
+
+    #[test]
+    fn branch_target_for_relative_jump() {
+        let instruction = Instruction::decode_bytes(&[0x20, 0xFC]);
+        assert_eq!(
+            instruction
+                .operation
+                .branch_target(0x0150, instruction.length),
+            Some(0x014E)
+        );
+    }
+
+    #[test]
+    fn decode_lengths_match_opcode_table() {
+        // Instruction lengths for every unprefixed opcode. CB counts as 2.
+        #[rustfmt::skip]
+        let lengths: [u8; 256] = [
+            1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+            2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+            2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+            2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+            1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+            1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1,
+            2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+            2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,
+        ];
+        for opcode in 0..=255u8 {
+            let instruction = Instruction::decode_bytes(&[opcode, 0, 0]);
+            assert_eq!(
+                instruction.length, lengths[opcode as usize],
+                "opcode {:#04x}",
+                opcode
+            );
+        }
+    }
+
+    // M-cycles for every unprefixed opcode, with conditional branches not
+    // taken. Illegal opcodes are 0.
+    #[rustfmt::skip]
+    const TIMINGS: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 2, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    // The same, with conditional branches taken.
+    #[rustfmt::skip]
+    const BRANCH_TIMINGS: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        3, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        3, 3, 2, 2, 3, 3, 3, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        5, 3, 4, 4, 6, 4, 2, 4, 5, 4, 4, 2, 6, 6, 2, 4,
+        5, 3, 4, 0, 6, 4, 2, 4, 5, 4, 4, 0, 6, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    #[test]
+    fn cycles_match_timing_table() {
+        for opcode in 0..=0xFFu8 {
+            if opcode == 0xCB {
+                continue;
+            }
+            let instruction = Instruction::decode_bytes(&[opcode, 0x00, 0x00]);
+            if let Operation::Illegal(_) = instruction.operation {
+                assert_eq!(TIMINGS[opcode as usize], 0, "opcode {:02x}", opcode);
+                continue;
+            }
+            assert_eq!(
+                instruction.cycles,
+                TIMINGS[opcode as usize] * 4,
+                "opcode {:02x}",
+                opcode
+            );
+            assert_eq!(
+                instruction.branch_cycles,
+                BRANCH_TIMINGS[opcode as usize] * 4,
+                "opcode {:02x} taken",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn cb_cycles_match_timing_table() {
+        for cb_opcode in 0..=0xFFu8 {
+            let instruction = Instruction::decode_bytes(&[0xCB, cb_opcode]);
+            // Only (HL) operands are slower, and BIT only reads it.
+            let expected = match (cb_opcode & 7, cb_opcode >> 6) {
+                (6, 1) => 3,
+                (6, _) => 4,
+                _ => 2,
+            };
+            assert_eq!(instruction.cycles, expected * 4, "cb {:02x}", cb_opcode);
+            assert_eq!(instruction.branch_cycles, instruction.cycles);
+        }
+    }
+}
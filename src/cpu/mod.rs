@@ -1,2 +1,3 @@
 mod alu;
 pub mod cpu;
+pub mod instruction;
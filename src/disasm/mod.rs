@@ -0,0 +1,301 @@
+// Linear disassembly of a byte range into rgbds syntax, using the CPU's own
+// decoder so the output always matches what the emulator would run.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use cpu::instruction::{Instruction, Operand8, Operation};
+
+pub mod recursive;
+
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    // None when the bytes can't be written back as an instruction, e.g. an
+    // instruction cut off by the end of the range.
+    pub instruction: Option<Instruction>,
+}
+
+// Decodes bytes one instruction after another, as if they were loaded at
+// origin.
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = origin.wrapping_add(offset as u16);
+        let instruction = Instruction::decode_bytes(&bytes[offset..]);
+        let length = instruction.length as usize;
+        if offset + length > bytes.len() {
+            lines.push(Line {
+                addr,
+                bytes: bytes[offset..].to_vec(),
+                instruction: None,
+            });
+            break;
+        }
+        let raw = bytes[offset..offset + length].to_vec();
+        lines.push(Line {
+            addr,
+            instruction: if assembles_to_same_bytes(&instruction, &raw) {
+                Some(instruction)
+            } else {
+                None
+            },
+            bytes: raw,
+        });
+        offset += length;
+    }
+    lines
+}
+
+// rgbasm always writes STOP as 0x10 0x00, so a STOP followed by anything
+// else has to stay as raw bytes to assemble back the same. Some versions of
+// rgbasm also turn LD with an address in high memory into LDH, and older ones
+// put a NOP after every HALT, so those are kept as raw bytes too.
+pub fn assembles_to_same_bytes(instruction: &Instruction, bytes: &[u8]) -> bool {
+    match instruction.operation {
+        Operation::Stop => bytes.get(1) == Some(&0),
+        Operation::Halt => false,
+        Operation::Ld(Operand8::IndImm(addr), _) | Operation::Ld(_, Operand8::IndImm(addr)) => {
+            addr < 0xFF00
+        }
+        Operation::Illegal(_) => false,
+        _ => true,
+    }
+}
+
+// Label names for every address jumped or called to from within the lines.
+// Call targets and the start get global labels. Jumps get local labels, unless
+// a global label between the jump and its target would put them in different
+// scopes, in which case the target is made global too. Global labels include
+// the bank, since every switchable bank is disassembled at 0x4000 and labels
+// have to be unique across the whole ROM.
+pub fn labels(lines: &[Line], bank: usize) -> BTreeMap<u16, String> {
+    let starts: BTreeSet<u16> = lines
+        .iter()
+        .filter(|line| line.instruction.is_some())
+        .map(|line| line.addr)
+        .collect();
+    let mut globals = BTreeSet::new();
+    let mut jumps = vec![];
+    if let Some(first) = lines.first() {
+        globals.insert(first.addr);
+    }
+    for line in lines {
+        let instruction = match line.instruction {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let target = match instruction
+            .operation
+            .branch_target(line.addr, instruction.length)
+        {
+            Some(target) if starts.contains(&target) => target,
+            _ => continue,
+        };
+        match instruction.operation {
+            Operation::Call(_, _) => {
+                globals.insert(target);
+            }
+            _ => jumps.push((line.addr, target)),
+        }
+    }
+
+    // Promoting a label to global can change the scope of others, so keep
+    // going until nothing changes.
+    let scope = |globals: &BTreeSet<u16>, addr: u16| globals.range(..=addr).next_back().cloned();
+    loop {
+        let promote: Vec<u16> = jumps
+            .iter()
+            .filter(|(from, to)| {
+                !globals.contains(to) && scope(&globals, *from) != scope(&globals, *to)
+            })
+            .map(|(_, to)| *to)
+            .collect();
+        if promote.is_empty() {
+            break;
+        }
+        globals.extend(promote);
+    }
+
+    let mut labels = BTreeMap::new();
+    for (_, target) in jumps {
+        if !globals.contains(&target) {
+            labels.insert(target, format!(".jr_{:04x}", target));
+        }
+    }
+    for addr in globals {
+        labels.insert(addr, format!("Label_{:03x}_{:04x}", bank, addr));
+    }
+    labels
+}
+
+// Writes a single line, using labels for branch targets that have one.
+pub fn format_line(line: &Line, labels: &BTreeMap<u16, String>) -> String {
+    let instruction = match line.instruction {
+        Some(instruction) => instruction,
+        None => return format_db(&line.bytes),
+    };
+    let target = instruction
+        .operation
+        .branch_target(line.addr, instruction.length)
+        .and_then(|target| labels.get(&target));
+    match target {
+        Some(label) => instruction.operation.with_target(label),
+        None => format!("{}", instruction),
+    }
+}
+
+pub fn format_db(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("${:02x}", byte)).collect();
+    format!("db {}", bytes.join(", "))
+}
+
+// Disassembles a byte range into rgbds assembly, with labels for jump and
+// call targets and the address and raw bytes of each instruction as a
+// comment.
+pub fn to_asm(bytes: &[u8], origin: u16, bank: usize) -> String {
+    let lines = disassemble(bytes, origin);
+    let labels = labels(&lines, bank);
+    let mut asm = String::new();
+    for line in &lines {
+        if let Some(label) = labels.get(&line.addr) {
+            if !label.starts_with('.') {
+                asm.push('\n');
+            }
+            writeln!(asm, "{}:", label).unwrap();
+        }
+        let raw: Vec<String> = line
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        writeln!(
+            asm,
+            "    {:<24} ; ${:04x}: {}",
+            format_line(line, &labels),
+            line.addr,
+            raw.join(" ")
+        )
+        .unwrap();
+    }
+    asm
+}
+
+// Disassembles a whole ROM, one section per 16KB bank. Bank 0 is always
+// mapped at 0x0000 and the others are switched in at 0x4000.
+pub fn rom_to_asm(rom: &[u8]) -> String {
+    let mut asm = String::new();
+    for (bank, bytes) in rom.chunks(0x4000).enumerate() {
+        if bank == 0 {
+            writeln!(asm, "SECTION \"ROM Bank $000\", ROM0[$0000]").unwrap();
+            asm.push_str(&to_asm(bytes, 0, 0));
+        } else {
+            writeln!(
+                asm,
+                "\nSECTION \"ROM Bank ${:03x}\", ROMX[$4000], BANK[${:x}]",
+                bank, bank
+            )
+            .unwrap();
+            asm.push_str(&to_asm(bytes, 0x4000, bank));
+        }
+    }
+    asm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_test_rom_program() {
+        // roms/test/ld.asm
+        let lines = disassemble(&[0x3E, 0x0A, 0x06, 0x05, 0x80, 0xA0, 0x76], 0);
+        let text: Vec<String> = lines
+            .iter()
+            .map(|line| format_line(line, &BTreeMap::new()))
+            .collect();
+        assert_eq!(
+            text,
+            vec!["ld a, 10", "ld b, 5", "add a, b", "and a, b", "db $76"]
+        );
+        assert_eq!(lines[2].addr, 4);
+    }
+
+    #[test]
+    fn rom_sections_per_bank() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4000] = 0xC9;
+        let asm = rom_to_asm(&rom);
+        assert!(asm.starts_with("SECTION \"ROM Bank $000\", ROM0[$0000]\n"));
+        assert!(asm.contains("SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]\n"));
+        assert!(asm.contains("ret                      ; $4000: c9"));
+    }
+
+    #[test]
+    fn labels_are_unique_across_banks() {
+        let asm = rom_to_asm(&vec![0; 0xC000]);
+        assert!(asm.contains("Label_000_0000:"));
+        assert!(asm.contains("Label_001_4000:"));
+        assert!(asm.contains("Label_002_4000:"));
+        let mut globals: Vec<&str> = asm
+            .lines()
+            .filter(|line| line.starts_with("Label_"))
+            .collect();
+        let count = globals.len();
+        globals.sort();
+        globals.dedup();
+        assert_eq!(globals.len(), count);
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        let lines = disassemble(&[0x00, 0xC3, 0x50], 0x100);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(format_line(&lines[1], &BTreeMap::new()), "db $c3, $50");
+    }
+
+    #[test]
+    fn stop_with_nonzero_byte_is_data() {
+        let lines = disassemble(&[0x10, 0x01, 0x10, 0x00], 0);
+        assert!(lines[0].instruction.is_none());
+        assert_eq!(format_line(&lines[1], &BTreeMap::new()), "stop");
+    }
+
+    #[test]
+    fn high_memory_ld_is_data() {
+        let lines = disassemble(&[0xEA, 0x80, 0xFF], 0);
+        assert_eq!(format_line(&lines[0], &BTreeMap::new()), "db $ea, $80, $ff");
+    }
+
+    #[test]
+    fn halt_is_data() {
+        // Older rgbasm would put a NOP between these.
+        let lines = disassemble(&[0x76, 0x3C], 0);
+        assert_eq!(format_line(&lines[0], &BTreeMap::new()), "db $76");
+        assert_eq!(format_line(&lines[1], &BTreeMap::new()), "inc a");
+    }
+
+    #[test]
+    fn jumps_use_local_labels() {
+        // .loop: dec b; jr nz, .loop; ret
+        let asm = to_asm(&[0x05, 0x20, 0xFD, 0xC9], 0x150, 0);
+        assert!(asm.contains("Label_000_0150:"));
+        assert!(asm.contains("jr nz, Label_000_0150"));
+
+        // nop; .loop: dec b; jr nz, .loop
+        let asm = to_asm(&[0x00, 0x05, 0x20, 0xFD], 0x150, 0);
+        assert!(asm.contains(".jr_0151:"));
+        assert!(asm.contains("jr nz, .jr_0151"));
+    }
+
+    #[test]
+    fn jumps_across_calls_get_global_labels() {
+        // nop; call .sub; jr .end; .sub: ret; .end: jr .start
+        let bytes = [0x00, 0xCD, 0x06, 0x01, 0x18, 0x01, 0xC9, 0x18, 0xF7];
+        let lines = disassemble(&bytes, 0x100);
+        let labels = labels(&lines, 0);
+        assert_eq!(labels.get(&0x106).unwrap(), "Label_000_0106");
+        // The jr at 0x104 is in the first scope but jumps past Label_000_0106.
+        assert_eq!(labels.get(&0x107).unwrap(), "Label_000_0107");
+    }
+}
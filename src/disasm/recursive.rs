@@ -0,0 +1,610 @@
+// Recursive traversal disassembly of a whole ROM. Starting from the entry
+// point, every jump, call and branch is followed to find out which bytes are
+// actually code, so data isn't mistaken for instructions like in a linear
+// sweep. The result can be written out as an .asm file that rgbasm and
+// rgblink build back into the same ROM.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use cpu::instruction::{Instruction, Operand8, Operation, Reg8};
+use disasm::{assembles_to_same_bytes, format_db};
+
+const BANK_SIZE: usize = 0x4000;
+const ENTRY_POINT: usize = 0x100;
+const INTERRUPT_VECTORS: [(usize, &str); 5] = [
+    (0x40, "VBlankInterrupt"),
+    (0x48, "LCDInterrupt"),
+    (0x50, "TimerInterrupt"),
+    (0x58, "SerialInterrupt"),
+    (0x60, "JoypadInterrupt"),
+];
+
+pub struct Analysis {
+    // The instruction starting at each ROM offset that was found to be code.
+    instructions: BTreeMap<usize, Instruction>,
+    // Which instruction each byte of code belongs to.
+    owners: Vec<Option<usize>>,
+    // Where each jump or call resolved to, by the offset of the instruction.
+    targets: BTreeMap<usize, usize>,
+    labels: BTreeMap<usize, String>,
+}
+
+// Code still to be traced: a ROM offset and the bank switched in at 0x4000
+// when it runs, if known.
+struct Work {
+    offset: usize,
+    bank: Option<usize>,
+}
+
+// ROM offset for a CPU address, given the bank switched in at 0x4000.
+fn rom_offset(addr: u16, bank: Option<usize>) -> Option<usize> {
+    match addr {
+        0x0000..=0x3FFF => Some(addr as usize),
+        0x4000..=0x7FFF => bank.map(|bank| bank * BANK_SIZE + addr as usize - 0x4000),
+        // RAM, which can't be traced statically.
+        _ => None,
+    }
+}
+
+// CPU address and bank of a ROM offset.
+fn cpu_addr(offset: usize) -> (usize, u16) {
+    let bank = offset / BANK_SIZE;
+    if bank == 0 {
+        (0, offset as u16)
+    } else {
+        (bank, 0x4000 + (offset % BANK_SIZE) as u16)
+    }
+}
+
+// Whether an operation might change A. Used to forget what's known about A
+// when tracking bank switches, so anything unclear counts as a change.
+fn writes_a(operation: &Operation) -> bool {
+    match *operation {
+        Operation::Ld(Operand8::Reg(Reg8::A), _) => true,
+        Operation::Ld(_, _) => false,
+        Operation::Nop
+        | Operation::Di
+        | Operation::Ei
+        | Operation::Ld16(_, _)
+        | Operation::LdImmSp(_)
+        | Operation::Push(_)
+        | Operation::Inc16(_)
+        | Operation::Dec16(_)
+        | Operation::Cp(_)
+        | Operation::Jp(_, _)
+        | Operation::Jr(_, _)
+        | Operation::Bit(_, _) => false,
+        Operation::Inc(operand)
+        | Operation::Dec(operand)
+        | Operation::Rlc(operand)
+        | Operation::Rrc(operand)
+        | Operation::Rl(operand)
+        | Operation::Rr(operand)
+        | Operation::Sla(operand)
+        | Operation::Sra(operand)
+        | Operation::Swap(operand)
+        | Operation::Srl(operand)
+        | Operation::Res(_, operand)
+        | Operation::Set(_, operand) => operand == Operand8::Reg(Reg8::A),
+        _ => true,
+    }
+}
+
+impl Analysis {
+    pub fn analyze(rom: &[u8]) -> Analysis {
+        let mut analysis = Analysis {
+            instructions: BTreeMap::new(),
+            owners: vec![None; rom.len()],
+            targets: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        let banks = (rom.len() + BANK_SIZE - 1) / BANK_SIZE;
+        // With only two banks there's nothing to switch, bank 1 is always in.
+        let default_bank = if banks <= 2 { Some(1) } else { None };
+
+        let mut queue = vec![];
+        if rom.len() > ENTRY_POINT {
+            analysis.label(ENTRY_POINT, "Entry".to_string());
+            queue.push(Work {
+                offset: ENTRY_POINT,
+                bank: default_bank,
+            });
+        }
+
+        // Interrupt vectors only hold code if interrupts are ever enabled,
+        // so only trace them once an EI or RETI has been found.
+        let mut traced_vectors = false;
+        loop {
+            while let Some(work) = queue.pop() {
+                analysis.trace(rom, work, banks, default_bank, &mut queue);
+            }
+            let enables_interrupts = analysis.instructions.values().any(|instruction| {
+                matches!(instruction.operation, Operation::Ei | Operation::Reti)
+            });
+            if traced_vectors || !enables_interrupts {
+                break;
+            }
+            traced_vectors = true;
+            for &(vector, name) in INTERRUPT_VECTORS.iter() {
+                if vector < rom.len() {
+                    analysis.label(vector, name.to_string());
+                    queue.push(Work {
+                        offset: vector,
+                        bank: default_bank,
+                    });
+                }
+            }
+        }
+
+        // Labels are only useful where there's an instruction to put them on.
+        let instructions = &analysis.instructions;
+        analysis
+            .labels
+            .retain(|offset, _| instructions.contains_key(offset));
+        analysis
+    }
+
+    // Follows code from one starting point until it jumps away, returns or
+    // runs into something already traced. Branch targets are queued.
+    fn trace(
+        &mut self,
+        rom: &[u8],
+        work: Work,
+        banks: usize,
+        default_bank: Option<usize>,
+        queue: &mut Vec<Work>,
+    ) {
+        let mut offset = work.offset;
+        let (code_bank, _) = cpu_addr(offset);
+        // Code in a switchable bank can only jump around within that bank.
+        let mut bank = if code_bank == 0 {
+            work.bank
+        } else {
+            Some(code_bank)
+        };
+        // The value in A, if known, to spot bank switches.
+        let mut a: Option<u8> = None;
+
+        loop {
+            if offset >= rom.len() || self.instructions.contains_key(&offset) {
+                return;
+            }
+            let instruction = Instruction::decode_bytes(&rom[offset..]);
+            let length = instruction.length as usize;
+            let end = offset + length;
+            // Stop at anything that overlaps other code or runs off the end
+            // of its bank, since it can't be written out as an instruction.
+            if end > rom.len()
+                || (offset / BANK_SIZE) != ((end - 1) / BANK_SIZE)
+                || self.owners[offset..end].iter().any(|owner| owner.is_some())
+            {
+                return;
+            }
+            self.instructions.insert(offset, instruction);
+            for owner in &mut self.owners[offset..end] {
+                *owner = Some(offset);
+            }
+
+            let (_, addr) = cpu_addr(offset);
+            let operation = instruction.operation;
+            let mut target_bank = bank.or(default_bank);
+            match operation {
+                Operation::Ld(Operand8::Reg(Reg8::A), Operand8::Imm(val)) => a = Some(val),
+                Operation::Xor(Operand8::Reg(Reg8::A)) => a = Some(0),
+                // Writes to 0x2000-0x3FFF select the ROM bank on most MBCs,
+                // where bank 0 selects bank 1.
+                Operation::Ld(Operand8::IndImm(0x2000..=0x3FFF), Operand8::Reg(Reg8::A))
+                    if code_bank == 0 =>
+                {
+                    bank = a.map(|val| (val as usize % banks.max(1)).max(1));
+                    target_bank = bank.or(default_bank);
+                }
+                _ => {
+                    if writes_a(&operation) {
+                        a = None;
+                    }
+                }
+            }
+
+            if let Some(target) = operation.branch_target(addr, instruction.length) {
+                if let Some(target_offset) = rom_offset(target, target_bank) {
+                    if target_offset < rom.len() {
+                        let kind = match operation {
+                            Operation::Call(_, _) => "Call",
+                            _ => "Jump",
+                        };
+                        let (target_bank_number, _) = cpu_addr(target_offset);
+                        self.label(
+                            target_offset,
+                            format!("{}_{:03x}_{:04x}", kind, target_bank_number, target),
+                        );
+                        self.targets.insert(offset, target_offset);
+                        queue.push(Work {
+                            offset: target_offset,
+                            bank,
+                        });
+                    }
+                }
+            }
+            if let Operation::Rst(vector) = operation {
+                self.label(vector as usize, format!("Rst_{:02x}", vector));
+                queue.push(Work {
+                    offset: vector as usize,
+                    bank,
+                });
+            }
+
+            let falls_through = match operation {
+                Operation::Jp(None, _)
+                | Operation::Jr(None, _)
+                | Operation::JpHl
+                | Operation::Ret(None)
+                | Operation::Reti
+                | Operation::Illegal(_) => false,
+                // Whatever gets called might use A.
+                Operation::Call(_, _) | Operation::Rst(_) => {
+                    a = None;
+                    true
+                }
+                _ => true,
+            };
+            if !falls_through {
+                return;
+            }
+
+            // Running off the end of bank 0 carries on in whichever bank is
+            // switched in, and off the end of that is RAM.
+            if end % BANK_SIZE == 0 {
+                if code_bank == 0 {
+                    if let Some(next) = rom_offset(0x4000, target_bank) {
+                        queue.push(Work { offset: next, bank });
+                    }
+                }
+                return;
+            }
+            offset = end;
+        }
+    }
+
+    // The first name given to an address wins, so entry points and vectors
+    // keep their names.
+    fn label(&mut self, offset: usize, name: String) {
+        self.labels.entry(offset).or_insert(name);
+    }
+
+    pub fn is_code(&self, offset: usize) -> bool {
+        self.owners.get(offset).is_some_and(|owner| owner.is_some())
+    }
+
+    pub fn instruction_at(&self, offset: usize) -> Option<&Instruction> {
+        self.instructions.get(&offset)
+    }
+
+    pub fn label_at(&self, offset: usize) -> Option<&String> {
+        self.labels.get(&offset)
+    }
+
+    // Writes out the ROM as rgbds assembly, with one section per bank. Code
+    // is written as instructions and everything else as db.
+    pub fn to_asm(&self, rom: &[u8]) -> String {
+        let mut asm = String::new();
+        writeln!(asm, "; Disassembled by gremulator").unwrap();
+        for (bank, bank_bytes) in rom.chunks(BANK_SIZE).enumerate() {
+            if bank == 0 {
+                writeln!(asm, "\nSECTION \"ROM Bank $000\", ROM0[$0000]").unwrap();
+            } else {
+                writeln!(
+                    asm,
+                    "\nSECTION \"ROM Bank ${:03x}\", ROMX[$4000], BANK[${:x}]",
+                    bank, bank
+                )
+                .unwrap();
+            }
+
+            let bank_start = bank * BANK_SIZE;
+            let bank_end = bank_start + bank_bytes.len();
+            let mut offset = bank_start;
+            while offset < bank_end {
+                if let Some(label) = self.labels.get(&offset) {
+                    writeln!(asm, "\n{}:", label).unwrap();
+                }
+                if let Some(instruction) = self.instructions.get(&offset) {
+                    let length = instruction.length as usize;
+                    writeln!(
+                        asm,
+                        "    {}",
+                        self.format_instruction(instruction, offset, &rom[offset..offset + length])
+                    )
+                    .unwrap();
+                    offset += length;
+                    continue;
+                }
+
+                // Data runs until the next code, at most 16 bytes to a line.
+                let mut end = offset + 1;
+                while end < bank_end && end - offset < 16 && !self.is_code(end) {
+                    end += 1;
+                }
+                writeln!(asm, "    {}", format_db(&rom[offset..end])).unwrap();
+                offset = end;
+            }
+        }
+        asm
+    }
+
+    fn format_instruction(&self, instruction: &Instruction, offset: usize, bytes: &[u8]) -> String {
+        if !assembles_to_same_bytes(instruction, bytes) {
+            return format!("{} ; {}", format_db(bytes), instruction);
+        }
+        let label = self
+            .targets
+            .get(&offset)
+            .and_then(|target| self.labels.get(target));
+        match label {
+            Some(label) => instruction.operation.with_target(label),
+            None => format!("{}", instruction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 32KB ROM with some code reachable from the entry point and the rest
+    // left as data.
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0xFF; 0x8000];
+        // Entry: nop; jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // Header bytes, which would be mistaken for code in a linear sweep.
+        rom[0x104..0x150].copy_from_slice(&[0xCE; 0x4C]);
+        // $0150: call $4000; .loop: dec b; jr nz, .loop; halt; jr .loop
+        rom[0x150..0x159].copy_from_slice(&[0xCD, 0x00, 0x40, 0x05, 0x20, 0xFD, 0x76, 0x18, 0xFA]);
+        // $4000: ld a, [$4010]; ret
+        rom[0x4000..0x4004].copy_from_slice(&[0xFA, 0x10, 0x40, 0xC9]);
+        rom
+    }
+
+    #[test]
+    fn traces_reachable_code_only() {
+        let rom = test_rom();
+        let analysis = Analysis::analyze(&rom);
+        assert!(analysis.is_code(0x100));
+        assert!(analysis.is_code(0x103));
+        assert!(!analysis.is_code(0x104));
+        assert!(analysis.is_code(0x150));
+        assert!(analysis.is_code(0x158));
+        assert!(!analysis.is_code(0x159));
+        assert!(analysis.is_code(0x4003));
+        assert!(!analysis.is_code(0x4004));
+        // Data read by code isn't code.
+        assert!(!analysis.is_code(0x4010));
+    }
+
+    #[test]
+    fn generates_labels() {
+        let analysis = Analysis::analyze(&test_rom());
+        assert_eq!(analysis.label_at(0x100).unwrap(), "Entry");
+        assert_eq!(analysis.label_at(0x150).unwrap(), "Jump_000_0150");
+        assert_eq!(analysis.label_at(0x153).unwrap(), "Jump_000_0153");
+        assert_eq!(analysis.label_at(0x4000).unwrap(), "Call_001_4000");
+    }
+
+    #[test]
+    fn follows_bank_switches() {
+        let mut rom = vec![0x00; 0x10000];
+        // Entry: ld a, 3; ld [$2000], a; call $4000; halt
+        rom[0x100..0x109].copy_from_slice(&[0x3E, 0x03, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x76]);
+        // Bank 3 at $4000: ret
+        rom[0xC000] = 0xC9;
+        let analysis = Analysis::analyze(&rom);
+        assert!(analysis.is_code(0xC000));
+        assert!(!analysis.is_code(0x4000));
+        assert_eq!(analysis.label_at(0xC000).unwrap(), "Call_003_4000");
+    }
+
+    #[test]
+    fn interrupt_vectors_traced_when_enabled() {
+        let mut rom = test_rom();
+        assert!(!Analysis::analyze(&rom).is_code(0x40));
+        // Entry: ei; jp $0150
+        rom[0x100] = 0xFB;
+        rom[0x40] = 0xD9;
+        let analysis = Analysis::analyze(&rom);
+        assert!(analysis.is_code(0x40));
+        assert_eq!(analysis.label_at(0x40).unwrap(), "VBlankInterrupt");
+    }
+
+    #[test]
+    fn asm_output_has_code_labels_and_data() {
+        let rom = test_rom();
+        let asm = Analysis::analyze(&rom).to_asm(&rom);
+        assert!(asm.contains("SECTION \"ROM Bank $000\", ROM0[$0000]"));
+        assert!(asm.contains("SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]"));
+        assert!(asm.contains("\nEntry:\n    nop\n    jp Jump_000_0150\n"));
+        assert!(asm.contains("    call Call_001_4000\n"));
+        assert!(asm.contains("    jr nz, Jump_000_0153\n"));
+        assert!(asm.contains("    db $ce, $ce"));
+    }
+
+    // Checks that the instructions and data written out cover every byte of
+    // the ROM exactly.
+    #[test]
+    fn asm_output_covers_every_byte() {
+        let rom = test_rom();
+        let asm = Analysis::analyze(&rom).to_asm(&rom);
+        let mut size = 0;
+        for line in asm.lines() {
+            let line = line.trim();
+            if line.starts_with("db ") {
+                let data = line.split(';').next().unwrap();
+                size += data.matches('$').count();
+            } else if line.starts_with(';') || line.starts_with("SECTION") || line.ends_with(':') {
+                continue;
+            } else if !line.is_empty() {
+                size += 1;
+            }
+        }
+        let instruction_bytes: usize = Analysis::analyze(&rom)
+            .instructions
+            .values()
+            .map(|instruction| instruction.length as usize - 1)
+            .sum();
+        assert_eq!(size + instruction_bytes, rom.len());
+    }
+
+    // Builds the assembly back into a ROM by finding the encoding of each
+    // instruction that the disassembler writes out the same way, so the
+    // round trip can be checked without rgbds.
+    fn assemble(asm: &str, size: usize) -> Vec<u8> {
+        let mut opcodes: Vec<Vec<u8>> = (0..=0xFFu8)
+            .filter(|&opcode| opcode != 0xCB)
+            .map(|opcode| vec![opcode])
+            .collect();
+        opcodes.extend((0..=0xFFu8).map(|opcode| vec![0xCB, opcode]));
+
+        // Every instruction's address, found by sizing each line. Only jumps
+        // and calls use labels, and their size doesn't depend on the target.
+        let mut labels = BTreeMap::new();
+        let mut rom = vec![0; size];
+        for pass in 0..2 {
+            let (mut offset, mut addr) = (0, 0u16);
+            for line in asm.lines() {
+                let line = line.split(';').next().unwrap().trim();
+                if line.is_empty() {
+                    continue;
+                }
+                if line.starts_with("SECTION") {
+                    let bank = line.split("BANK[$").nth(1).map_or(0, |bank| {
+                        usize::from_str_radix(&bank[..bank.len() - 1], 16).unwrap()
+                    });
+                    offset = bank * BANK_SIZE;
+                    addr = if bank == 0 { 0 } else { 0x4000 };
+                    continue;
+                }
+                if let Some(label) = line.strip_suffix(':') {
+                    labels.insert(label.to_string(), addr);
+                    continue;
+                }
+                let bytes = if let Some(data) = line.strip_prefix("db ") {
+                    data.split(", ")
+                        .map(|byte| u8::from_str_radix(&byte[1..], 16).unwrap())
+                        .collect()
+                } else if pass == 0 {
+                    let length = match line.split(' ').next().unwrap() {
+                        "jr" => 2,
+                        "jp" | "call" if line.split(' ').any(is_label) => 3,
+                        _ => encode(&opcodes, line, addr).len(),
+                    };
+                    vec![0; length]
+                } else {
+                    let line: Vec<String> = line
+                        .split(' ')
+                        .map(|word| match labels.get(word) {
+                            Some(target) => format!("${:04x}", target),
+                            None => word.to_string(),
+                        })
+                        .collect();
+                    encode(&opcodes, &line.join(" "), addr)
+                };
+                rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                offset += bytes.len();
+                addr = addr.wrapping_add(bytes.len() as u16);
+            }
+        }
+        rom
+    }
+
+    fn is_label(word: &str) -> bool {
+        word.chars().next().is_some_and(|c| c.is_ascii_uppercase())
+    }
+
+    // The bytes of the instruction at addr that's written out as text.
+    fn encode(opcodes: &[Vec<u8>], text: &str, addr: u16) -> Vec<u8> {
+        let mnemonic = text.split(' ').next().unwrap();
+        for opcode in opcodes {
+            let length = Instruction::decode_bytes(opcode).length as usize;
+            let candidates: Vec<Vec<u8>> = match length - opcode.len() {
+                0 => vec![opcode.clone()],
+                1 => (0..=0xFF)
+                    .map(|imm| [&opcode[..], &[imm]].concat())
+                    .collect(),
+                _ => match text
+                    .rsplit('$')
+                    .next()
+                    .and_then(|imm| u16::from_str_radix(&imm[..4.min(imm.len())], 16).ok())
+                {
+                    Some(imm) => vec![[&opcode[..], &imm.to_le_bytes()].concat()],
+                    None => continue,
+                },
+            };
+            for bytes in candidates {
+                let instruction = Instruction::decode_bytes(&bytes);
+                if !format!("{}", instruction).starts_with(mnemonic) {
+                    break;
+                }
+                let target = instruction
+                    .operation
+                    .branch_target(addr, instruction.length)
+                    .map(|target| {
+                        instruction
+                            .operation
+                            .with_target(&format!("${:04x}", target))
+                    });
+                if format!("{}", instruction) == text || target.as_deref() == Some(text) {
+                    return bytes;
+                }
+            }
+        }
+        panic!("no encoding for {}", text);
+    }
+
+    #[test]
+    fn asm_output_assembles_back_into_the_rom() {
+        let mut rom = test_rom();
+        // Entry: ei; jp $0150, with a vector that reads high memory.
+        rom[0x100] = 0xFB;
+        // $0040: ld a, [$ff44]; ldh a, [$ff44]; reti
+        rom[0x40..0x46].copy_from_slice(&[0xFA, 0x44, 0xFF, 0xF0, 0x44, 0xD9]);
+        let asm = Analysis::analyze(&rom).to_asm(&rom);
+        assert!(asm.contains("    db $76 ; halt\n"));
+        assert!(asm.contains("    db $fa, $44, $ff ; ld a, [$ff44]\n"));
+        assert_eq!(assemble(&asm, rom.len()), rom);
+    }
+
+    // Builds the output with rgbds, when it's installed.
+    #[test]
+    fn asm_output_builds_with_rgbds() {
+        use std::env;
+        use std::fs;
+        use std::process::Command;
+
+        let installed = |tool: &str| Command::new(tool).arg("--version").output().is_ok();
+        if !installed("rgbasm") || !installed("rgblink") {
+            return;
+        }
+        let rom = test_rom();
+        let dir = env::temp_dir().join(format!("gremulator-rgbds-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (asm, obj, gb) = (dir.join("rom.asm"), dir.join("rom.o"), dir.join("rom.gb"));
+        fs::write(&asm, Analysis::analyze(&rom).to_asm(&rom)).unwrap();
+        let assembled = Command::new("rgbasm")
+            .arg("-o")
+            .arg(&obj)
+            .arg(&asm)
+            .status()
+            .unwrap();
+        assert!(assembled.success());
+        let linked = Command::new("rgblink")
+            .arg("-o")
+            .arg(&gb)
+            .arg(&obj)
+            .status()
+            .unwrap();
+        assert!(linked.success());
+        let built = fs::read(&gb).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(built, rom);
+    }
+}
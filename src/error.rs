@@ -0,0 +1,100 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+// Anything that stops the emulator from carrying on. Returned instead of
+// panicking so that tools embedding the emulator can report and recover.
+#[derive(Debug)]
+pub enum EmulatorError {
+    // One of the opcodes that doesn't exist on the SM83, with the address it
+    // was fetched from.
+    IllegalOpcode {
+        pc: u16,
+        opcode: u8,
+    },
+    // An address that maps onto the cartridge, but past the end of the ROM.
+    OutOfBoundsAccess {
+        addr: u16,
+        rom_size: usize,
+    },
+    RomLoadError(io::Error),
+    EmptyRom,
+    // Too short to hold the cartridge header, which ends at 0x14F, or with
+    // the header there but fewer banks than it says the ROM has.
+    TruncatedRom {
+        size: usize,
+        header_size: Option<usize>,
+    },
+    // An image for the Pocket Camera that couldn't be read or decoded.
+    ImageLoadError(String),
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::IllegalOpcode { pc, opcode } => {
+                write!(f, "Illegal opcode ${:02x} at ${:04x}", opcode, pc)
+            }
+            EmulatorError::OutOfBoundsAccess { addr, rom_size } => write!(
+                f,
+                "Access to ${:04x} is past the end of the {} byte ROM",
+                addr, rom_size
+            ),
+            EmulatorError::RomLoadError(ref err) => write!(f, "Unable to load ROM: {}", err),
+            EmulatorError::EmptyRom => write!(f, "The ROM is empty"),
+            EmulatorError::TruncatedRom {
+                size,
+                header_size: None,
+            } => write!(
+                f,
+                "The ROM is only {} bytes, too short to have a cartridge header",
+                size
+            ),
+            EmulatorError::TruncatedRom {
+                size,
+                header_size: Some(header_size),
+            } => write!(
+                f,
+                "The ROM is only {} bytes, but its header says it's {} bytes",
+                size, header_size
+            ),
+            EmulatorError::ImageLoadError(ref err) => write!(f, "Unable to load image: {}", err),
+        }
+    }
+}
+
+impl error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            EmulatorError::RomLoadError(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(err: io::Error) -> EmulatorError {
+        EmulatorError::RomLoadError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn illegal_opcode_message() {
+        let err = EmulatorError::IllegalOpcode {
+            pc: 0x0150,
+            opcode: 0xD3,
+        };
+        assert_eq!(err.to_string(), "Illegal opcode $d3 at $0150");
+    }
+
+    #[test]
+    fn io_errors_are_rom_load_errors() {
+        let err: EmulatorError = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert!(matches!(err, EmulatorError::RomLoadError(_)));
+        assert!(error::Error::source(&err).is_some());
+    }
+}
@@ -0,0 +1,272 @@
+extern crate log;
+
+use self::log::info;
+use std::path::Path;
+
+use cartridge::Cartridge;
+use cpu::cpu::CPU;
+use cpu::instruction::Instruction;
+use error::EmulatorError;
+use joypad::Button;
+use mmu::MMU;
+use model::Model;
+
+pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// 154 lines of 456 dots each.
+pub const FRAME_DOTS: u32 = 70224;
+
+// The whole machine: the CPU and the bus, which owns the PPU, timer, APU,
+// joypad, serial port and cartridge. Frontends and tests should all drive
+// the emulator through this. There's no sound output yet: the APU only has
+// its registers and length counters, so NR52 shows channels stopping when
+// their length runs out, but not when a sweep or envelope would stop them.
+pub struct GameBoy {
+    cpu: CPU,
+    mmu: MMU,
+    model: Model,
+    // Dots into the current frame. Instructions don't line up with frames,
+    // so whatever a frame overran by counts towards the next one.
+    frame_dots: u32,
+}
+
+impl GameBoy {
+    pub fn new(cartridge: Cartridge) -> GameBoy {
+        GameBoy::with_model(cartridge, Model::default())
+    }
+
+    // Starts just after the model's boot ROM has handed over to the
+    // cartridge.
+    pub fn with_model(cartridge: Cartridge, model: Model) -> GameBoy {
+        info!("Created new {:?} Game Boy", model);
+        let header_checksum = cartridge.header().header_checksum;
+        GameBoy {
+            cpu: CPU::new(model, header_checksum),
+            mmu: MMU::new(model, cartridge),
+            model,
+            frame_dots: 0,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<GameBoy, EmulatorError> {
+        Ok(GameBoy::new(Cartridge::from_path(path)?))
+    }
+
+    pub fn from_bytes<R: Into<Vec<u8>>>(rom: R) -> Result<GameBoy, EmulatorError> {
+        Ok(GameBoy::new(Cartridge::from_bytes(rom)?))
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    // Memory as the CPU sees it, without any time passing.
+    pub fn read(&self, addr: u16) -> Result<u8, EmulatorError> {
+        self.mmu.fetch(addr)
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) -> Result<(), EmulatorError> {
+        self.mmu.set_mem_addr(addr, val)
+    }
+
+    pub fn decode_at(&self, addr: u16) -> Result<Instruction, EmulatorError> {
+        self.cpu.decode_at(&self.mmu, addr)
+    }
+
+    // The last frame the PPU drew, SCREEN_WIDTH by SCREEN_HEIGHT shades from
+    // 0 (white) to 3 (black).
+    pub fn frame(&self) -> &[u8] {
+        self.mmu.frame()
+    }
+
+    // Every byte sent out of the link port so far.
+    pub fn serial_output(&self) -> &[u8] {
+        self.mmu.serial_output()
+    }
+
+    // Whether a CGB has switched to double speed. Cycles are always counted
+    // at the CPU's speed, so only half as much time passes for each one.
+    pub fn double_speed(&self) -> bool {
+        self.mmu.double_speed()
+    }
+
+    // The cartridge's battery backed RAM, and anything else it keeps with
+    // it, to write out between runs. None for cartridges without a battery.
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mmu.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mmu.load_save_data(data);
+    }
+
+    // Tilts cartridges with an accelerometer, in g. Positive x is to the
+    // right and positive y towards the player. Games read whatever this
+    // was when they last latched it.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu.set_tilt(x, y);
+    }
+
+    // Whether the cartridge's rumble motor is running. Only MBC5 rumble
+    // cartridges have one.
+    pub fn rumble(&self) -> bool {
+        self.mmu.rumble()
+    }
+
+    // Calls the handler with the motor's new state whenever it starts or
+    // stops, for frontends that would rather not poll.
+    pub fn on_rumble<F: FnMut(bool) + 'static>(&mut self, handler: F) {
+        self.mmu.on_rumble(Box::new(handler));
+    }
+
+    pub fn press_button(&mut self, button: Button) {
+        self.mmu.press_button(button);
+    }
+
+    pub fn release_button(&mut self, button: Button) {
+        self.mmu.release_button(button);
+    }
+
+    // Runs one instruction, or dispatches one interrupt, and returns the
+    // number of cycles taken.
+    pub fn step(&mut self) -> Result<u8, EmulatorError> {
+        let cycles = self.cpu.cycle(&mut self.mmu)?;
+        self.frame_dots += if self.double_speed() {
+            cycles as u32 / 2
+        } else {
+            cycles as u32
+        };
+        Ok(cycles)
+    }
+
+    // Whether the CPU will never run another instruction: it's locked up
+    // after an illegal opcode, or halted with no interrupts enabled in IE to
+    // wake it. A HALT only ends when an enabled interrupt is requested,
+    // whether or not IME is set, so with IE clear nothing can end it.
+    pub fn stuck(&self) -> bool {
+        self.cpu.locked || (self.cpu.halted && self.mmu.fetch(0xFFFF).unwrap_or(0) & 0x1F == 0)
+    }
+
+    // Runs until a frame's worth of time has passed.
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        while self.frame_dots < FRAME_DOTS {
+            self.step()?;
+        }
+        self.frame_dots -= FRAME_DOTS;
+        Ok(())
+    }
+
+    // Steps until the predicate holds, checking it before each step, and
+    // returns the number of cycles run.
+    pub fn run_until<F>(&mut self, mut done: F) -> Result<u64, EmulatorError>
+    where
+        F: FnMut(&GameBoy) -> bool,
+    {
+        let mut cycles = 0;
+        while !done(self) {
+            cycles += self.step()? as u64;
+        }
+        Ok(cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Loads a program into work RAM and points the CPU at it, with the
+    // VBlank request left by the boot ROM cleared.
+    fn gameboy_with_program(program: &[u8]) -> GameBoy {
+        let mut gameboy = GameBoy::from_bytes(vec![0; 0x8000]).unwrap();
+        gameboy.write(0xFF0F, 0).unwrap();
+        for (i, byte) in program.iter().enumerate() {
+            gameboy.write(0xC000 + i as u16, *byte).unwrap();
+        }
+        gameboy.cpu_mut().registers.pc = 0xC000;
+        gameboy
+    }
+
+    #[test]
+    fn run_frame_runs_every_line_once() {
+        // JR -2, which takes 12 cycles and divides a frame exactly.
+        let mut gameboy = gameboy_with_program(&[0x18, 0xFE]);
+        gameboy.run_frame().unwrap();
+        assert_eq!(gameboy.read(0xFF44).unwrap(), 0);
+        assert_eq!(gameboy.read(0xFF0F).unwrap() & 0x1F, 0x01);
+        gameboy.write(0xFF0F, 0).unwrap();
+        gameboy.run_frame().unwrap();
+        assert_eq!(gameboy.read(0xFF44).unwrap(), 0);
+        assert_eq!(gameboy.read(0xFF0F).unwrap() & 0x1F, 0x01);
+    }
+
+    #[test]
+    fn run_until_stops_when_the_predicate_holds() {
+        // INC A; JR -3
+        let mut gameboy = gameboy_with_program(&[0x3C, 0x18, 0xFD]);
+        gameboy.cpu_mut().registers.a = 0;
+        let cycles = gameboy
+            .run_until(|gameboy| gameboy.cpu().registers.a == 3)
+            .unwrap();
+        assert_eq!(cycles, 4 + 12 + 4 + 12 + 4);
+        assert_eq!(gameboy.run_until(|_| true).unwrap(), 0);
+    }
+
+    #[test]
+    fn rumble_changes_reach_the_handler() {
+        let mut rom = vec![0; 0x8000];
+        // MBC5+RUMBLE
+        rom[0x147] = 0x1C;
+        let mut gameboy = GameBoy::from_bytes(rom).unwrap();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let handler_changes = changes.clone();
+        gameboy.on_rumble(move |on| handler_changes.borrow_mut().push(on));
+        gameboy.write(0x4000, 0x08).unwrap();
+        assert!(gameboy.rumble());
+        gameboy.write(0x4000, 0x09).unwrap();
+        gameboy.write(0x4000, 0x01).unwrap();
+        assert!(!gameboy.rumble());
+        assert_eq!(*changes.borrow(), vec![true, false]);
+    }
+
+    #[test]
+    fn serial_output_is_collected() {
+        // LD A,'o'; LDH (0x01),A; LD A,0x81; LDH (0x02),A; HALT
+        let program = [0x3E, b'o', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x76];
+        let mut gameboy = gameboy_with_program(&program);
+        gameboy.write(0xFFFF, 0x08).unwrap();
+        gameboy.run_until(|gameboy| gameboy.cpu().halted).unwrap();
+        assert_eq!(gameboy.serial_output(), b"o");
+        // The transfer finishing wakes the CPU back up.
+        gameboy.run_until(|gameboy| !gameboy.cpu().halted).unwrap();
+        assert_eq!(gameboy.read(0xFF01).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn halt_is_only_stuck_without_interrupts_enabled() {
+        // HALT; INC A; HALT
+        let mut gameboy = gameboy_with_program(&[0x76, 0x3C, 0x76]);
+        gameboy.cpu_mut().registers.a = 0;
+        // The timer overflowing wakes the first HALT, with IME off.
+        gameboy.write(0xFFFF, 0x04).unwrap();
+        gameboy.write(0xFF07, 0x05).unwrap();
+        gameboy.step().unwrap();
+        assert!(gameboy.cpu().halted);
+        assert!(!gameboy.stuck());
+        gameboy
+            .run_until(|gameboy| gameboy.cpu().registers.a == 1)
+            .unwrap();
+        gameboy.write(0xFFFF, 0x00).unwrap();
+        gameboy.run_until(|gameboy| gameboy.cpu().halted).unwrap();
+        assert!(gameboy.stuck());
+    }
+}
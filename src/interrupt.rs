@@ -0,0 +1,60 @@
+// Interrupt sources, in priority order. Each one has a bit in IE (0xFFFF)
+// and IF (0xFF0F) and a fixed address the CPU jumps to when serving it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+pub const ALL: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    pub fn bit(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 1,
+            Interrupt::LcdStat => 1 << 1,
+            Interrupt::Timer => 1 << 2,
+            Interrupt::Serial => 1 << 3,
+            Interrupt::Joypad => 1 << 4,
+        }
+    }
+
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+
+    // The highest priority interrupt set in both masks, if any.
+    pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+        ALL.iter().cloned().find(|i| pending & i.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highest_priority_prefers_lowest_bit() {
+        assert_eq!(Interrupt::highest_priority(0), None);
+        assert_eq!(Interrupt::highest_priority(0b10100), Some(Interrupt::Timer));
+        assert_eq!(
+            Interrupt::highest_priority(0b11111),
+            Some(Interrupt::VBlank)
+        );
+    }
+}
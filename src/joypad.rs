@@ -0,0 +1,136 @@
+// The P1 register at 0xFF00. Bits 4 and 5 select the direction keys and the
+// buttons, and the low nibble reads back the selected keys, 0 when pressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Whether the key is read through the buttons half of P1, and which of
+    // the four lines it pulls low.
+    fn line(self) -> (bool, u8) {
+        match self {
+            Button::Right => (false, 0x01),
+            Button::Left => (false, 0x02),
+            Button::Up => (false, 0x04),
+            Button::Down => (false, 0x08),
+            Button::A => (true, 0x01),
+            Button::B => (true, 0x02),
+            Button::Select => (true, 0x04),
+            Button::Start => (true, 0x08),
+        }
+    }
+}
+
+pub struct Joypad {
+    // Pressed keys, as bits in the same layout as the P1 lines.
+    directions: u8,
+    buttons: u8,
+    // Bits 4 and 5 as last written. A 0 selects that half.
+    select: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            directions: 0,
+            buttons: 0,
+            select: 0x30,
+        }
+    }
+
+    pub fn fetch(&self) -> u8 {
+        0xC0 | self.select | (!self.pressed_lines() & 0x0F)
+    }
+
+    pub fn set(&mut self, val: u8) {
+        self.select = val & 0x30;
+    }
+
+    // Returns whether one of the lines went low, which requests the joypad
+    // interrupt.
+    pub fn press(&mut self, button: Button) -> bool {
+        let before = self.pressed_lines();
+        match button.line() {
+            (true, bit) => self.buttons |= bit,
+            (false, bit) => self.directions |= bit,
+        }
+        self.pressed_lines() & !before != 0
+    }
+
+    pub fn release(&mut self, button: Button) {
+        match button.line() {
+            (true, bit) => self.buttons &= !bit,
+            (false, bit) => self.directions &= !bit,
+        }
+    }
+
+    // Whether any of P1's input lines is low, which is what brings the CPU
+    // out of STOP.
+    pub fn any_line_low(&self) -> bool {
+        self.pressed_lines() != 0
+    }
+
+    fn pressed_lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & 0x10 == 0 {
+            lines |= self.directions;
+        }
+        if self.select & 0x20 == 0 {
+            lines |= self.buttons;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_pressed_reads_all_ones() {
+        let mut joypad = Joypad::new();
+        assert_eq!(joypad.fetch(), 0xFF);
+        joypad.set(0x00);
+        assert_eq!(joypad.fetch(), 0xCF);
+    }
+
+    #[test]
+    fn only_selected_keys_are_read() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Start);
+        joypad.press(Button::Left);
+        // Buttons
+        joypad.set(0x10);
+        assert_eq!(joypad.fetch(), 0xD7);
+        // Directions
+        joypad.set(0x20);
+        assert_eq!(joypad.fetch(), 0xED);
+        joypad.release(Button::Left);
+        assert_eq!(joypad.fetch(), 0xEF);
+    }
+
+    #[test]
+    fn pressing_a_selected_key_requests_an_interrupt() {
+        let mut joypad = Joypad::new();
+        assert!(!joypad.press(Button::A));
+        assert!(!joypad.any_line_low());
+        joypad.set(0x10);
+        assert!(joypad.any_line_low());
+        assert!(!joypad.press(Button::A));
+        assert!(joypad.press(Button::B));
+    }
+}
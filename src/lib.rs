@@ -1,6 +1,21 @@
 #![crate_name = "gremulator"]
+// Hardware names (CPU, MMU, MBC) read better in caps, and cpu::cpu is fine.
+#![allow(clippy::upper_case_acronyms, clippy::module_inception)]
 
+mod apu;
+pub mod camera;
+pub mod cartridge;
+pub mod clock;
 pub mod cpu;
+pub mod disasm;
+pub mod error;
+pub mod gameboy;
+pub mod interrupt;
+pub mod joypad;
 mod mbc;
 mod mmu;
+pub mod model;
+mod ppu;
 mod register;
+mod serial;
+mod timer;
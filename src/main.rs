@@ -3,19 +3,120 @@ extern crate gremulator;
 extern crate log;
 
 use log::{info, trace};
-use std::io::Error;
+use std::env;
+use std::error;
+use std::fs;
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::Path;
+use std::rc::Rc;
 
-use gremulator::cpu::cpu::CPU;
+use gremulator::camera::PngImage;
+use gremulator::cartridge::info::RomInfo;
+use gremulator::cartridge::Cartridge;
+use gremulator::disasm;
+use gremulator::disasm::recursive::Analysis;
+use gremulator::gameboy::GameBoy;
 
-fn main() -> Result<(), Error> {
+const USAGE: &str = "USAGE:
+    gremulator run <rom> [--camera <image.png>]
+                              Run a ROM file, with battery saves in <rom>.sav.
+                              The Game Boy Camera sees the image if given, or
+                              a test pattern
+    gremulator disasm <rom>   Print the disassembly of a ROM file
+    gremulator analyze <rom> [out.asm]
+                              Trace the code in a ROM file and write it out as
+                              assembly that rgbasm can build back into the ROM,
+                              in <rom>.disasm.asm if no output is given
+    gremulator info <rom> [--json]
+                              Print what the ROM's header says, its checksums
+                              and hashes";
+
+fn main() -> Result<(), Box<dyn error::Error>> {
     env_logger::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|arg| arg.as_str()) {
+        Some("run") => match (
+            args.get(1),
+            args.get(2).map(|arg| arg.as_str()),
+            args.get(3),
+        ) {
+            (Some(path), None, None) => run(path, None)?,
+            (Some(path), Some("--camera"), Some(image)) => run(path, Some(image))?,
+            _ => usage()?,
+        },
+        Some("disasm") => match args.get(1) {
+            Some(path) => disassemble(path)?,
+            None => usage()?,
+        },
+        Some("analyze") => match args.get(1) {
+            Some(path) => analyze(path, args.get(2))?,
+            None => usage()?,
+        },
+        Some("info") => match (args.get(1), args.get(2).map(|arg| arg.as_str())) {
+            (Some(path), None) => info(path, false)?,
+            (Some(path), Some("--json")) => info(path, true)?,
+            _ => usage()?,
+        },
+        _ => usage()?,
+    }
+    Ok(())
+}
+
+fn usage() -> Result<(), Error> {
+    eprintln!("{}", USAGE);
+    Err(Error::new(ErrorKind::InvalidInput, "Invalid arguments"))
+}
+
+fn run(path: &str, camera: Option<&String>) -> Result<(), Box<dyn error::Error>> {
     info!("Gremulator successfully started");
-    let mut cpu = CPU::new()?;
-    while !cpu.halted {
-        cpu.cycle();
+    let mut cartridge = Cartridge::from_path(path)?;
+    if let Some(camera) = camera {
+        cartridge = cartridge.with_camera(Rc::new(PngImage::from_path(camera)?));
+    }
+    let mut gameboy = GameBoy::new(cartridge);
+    // Battery saves go next to the ROM.
+    let save_path = Path::new(path).with_extension("sav");
+    if let Ok(save) = fs::read(&save_path) {
+        gameboy.load_save_data(&save);
+        info!("Loaded save from {}", save_path.display());
+    }
+    // Games HALT to wait for interrupts all the time, so keep going until
+    // nothing could ever wake the CPU.
+    while !gameboy.stuck() {
+        gameboy.step()?;
         // Useful to debug for now.
-        trace!("Registers after cycle: {}", cpu.registers);
+        trace!("Registers after step: {}", gameboy.cpu().registers);
     }
-    info!("Gremulator halted! Exiting...");
+    info!("Gremulator halted for good! Exiting...");
+    if let Some(save) = gameboy.save_data() {
+        fs::write(&save_path, save)?;
+        info!("Wrote save to {}", save_path.display());
+    }
+    Ok(())
+}
+
+fn disassemble(path: &str) -> Result<(), Error> {
+    let rom = fs::read(path)?;
+    io::stdout().write_all(disasm::rom_to_asm(&rom).as_bytes())
+}
+
+// Writes to the given file, or <rom>.disasm.asm next to the ROM so a
+// hand-written <rom>.asm is never overwritten.
+fn analyze(path: &str, out: Option<&String>) -> Result<(), Error> {
+    let rom = fs::read(path)?;
+    let asm = Analysis::analyze(&rom).to_asm(&rom);
+    let out = match out {
+        Some(out) => Path::new(out).to_path_buf(),
+        None => Path::new(path).with_extension("disasm.asm"),
+    };
+    fs::write(&out, asm)?;
+    info!("Wrote disassembly to {}", out.display());
+    Ok(())
+}
+
+fn info(path: &str, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let info = RomInfo::new(&fs::read(path)?)?;
+    let out = if json { info.to_json() } else { info.to_text() };
+    io::stdout().write_all(out.as_bytes())?;
     Ok(())
 }
@@ -0,0 +1,121 @@
+extern crate log;
+
+use self::log::warn;
+
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Hudson's MBC1 lookalike, with an infrared LED and sensor that can be
+// mapped at 0xA000 in place of RAM. There's nothing on the other end of the
+// infrared, so the sensor never sees any light.
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // 0x0E to 0x0000-0x1FFF maps the infrared, anything else maps RAM. RAM
+    // doesn't need enabling.
+    ir_mode: bool,
+    // 0x2000-0x3FFF. Writing 0 selects 1, like MBC1.
+    rom_bank: u8,
+    // 0x4000-0x5FFF
+    ram_bank: u8,
+    ir_warned: bool,
+}
+
+impl HuC1 {
+    pub fn new(cartridge: Cartridge) -> HuC1 {
+        HuC1 {
+            ram: super::cartridge_ram(&cartridge),
+            battery: cartridge.header().cartridge_type.battery,
+            rom: cartridge.into_rom(),
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_warned: false,
+        }
+    }
+}
+
+impl super::MBC for HuC1 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ir_mode = val & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = (val & 0x3F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = val & 0x03,
+            _ => (),
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            // Bit 0 clear is no light.
+            return 0xC0;
+        }
+        match super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if self.ir_mode {
+            if val & 0x01 != 0 && !self.ir_warned {
+                warn!("HuC1 infrared isn't supported, nothing will see the signal");
+                self.ir_warned = true;
+            }
+            return;
+        }
+        if let Some(index) = super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    #[test]
+    fn banks_and_ram_need_no_enable() {
+        // 32KB of RAM
+        let mut mbc = HuC1::new(test_cartridge(0xFF, 0x05, 0x03));
+        mbc.set_rom(0x2000, 0x3F);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x3F);
+        mbc.set_rom(0x4000, 0x02);
+        mbc.set_ram(0xA000, 0x42);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+        mbc.set_rom(0x4000, 0x00);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x00);
+    }
+
+    #[test]
+    fn infrared_sees_no_light() {
+        let mut mbc = HuC1::new(test_cartridge(0xFF, 0x05, 0x03));
+        mbc.set_ram(0xA000, 0x42);
+        mbc.set_rom(0x0000, 0x0E);
+        assert_eq!(mbc.fetch_ram(0xA000), 0xC0);
+        // Turning the LED on doesn't reach RAM.
+        mbc.set_ram(0xA000, 0x01);
+        assert!(mbc.ir_warned);
+        mbc.set_rom(0x0000, 0x0A);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+    }
+}
@@ -0,0 +1,292 @@
+extern crate log;
+
+use self::log::warn;
+use std::rc::Rc;
+
+use cartridge::Cartridge;
+use clock::Clock;
+use error::EmulatorError;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+// Hudson's mapper with a real time clock, infrared and a speaker. The
+// register at 0x0000-0x1FFF picks what 0xA000-0xBFFF is: RAM, or one of
+// the ports for talking to the clock's microcontroller. Like HuC1 the
+// infrared never sees any light, and the speaker is silent.
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // 0x0 is read only RAM, 0xA RAM, 0xB clock commands, 0xC their results,
+    // 0xD the ready flag and 0xE infrared.
+    mode: u8,
+    // 0x2000-0x3FFF
+    rom_bank: u8,
+    // 0x4000-0x5FFF
+    ram_bank: u8,
+    rtc: HuC3Rtc,
+    ir_warned: bool,
+    tone_warned: bool,
+}
+
+impl HuC3 {
+    pub fn new(cartridge: Cartridge) -> HuC3 {
+        HuC3 {
+            ram: super::cartridge_ram(&cartridge),
+            battery: cartridge.header().cartridge_type.battery,
+            rtc: HuC3Rtc::new(cartridge.clock()),
+            rom: cartridge.into_rom(),
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_warned: false,
+            tone_warned: false,
+        }
+    }
+}
+
+impl super::MBC for HuC3 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = val & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x03,
+            _ => (),
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA => match super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+                Some(index) => self.ram[index],
+                None => 0xFF,
+            },
+            0xC => self.rtc.response(),
+            // Commands finish straight away.
+            0xD => 0x01,
+            0xE => 0xC0,
+            _ => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        match self.mode {
+            0xA => {
+                if let Some(index) = super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+                    self.ram[index] = val;
+                }
+            }
+            0xB => {
+                let unsupported = self.rtc.command(val);
+                if unsupported == Some(Unsupported::Tone) && !self.tone_warned {
+                    warn!("HuC3 speaker isn't supported, tones won't play");
+                    self.tone_warned = true;
+                }
+            }
+            0xE if val & 0x01 != 0 && !self.ir_warned => {
+                warn!("HuC3 infrared isn't supported, nothing will see the signal");
+                self.ir_warned = true;
+            }
+            _ => (),
+        }
+    }
+
+    // The clock is saved after the RAM, as the minutes it was last set to
+    // and when that was.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.rtc.set_minutes.to_le_bytes());
+        data.extend_from_slice(&self.rtc.set_at.to_le_bytes());
+        Some(data)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+        if let Some(rtc) = data.get(self.ram.len()..self.ram.len() + 16) {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&rtc[..8]);
+            self.rtc.set_minutes = u64::from_le_bytes(bytes);
+            bytes.copy_from_slice(&rtc[8..]);
+            self.rtc.set_at = u64::from_le_bytes(bytes);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unsupported {
+    Tone,
+}
+
+// The microcontroller behind the clock, which is talked to a nibble at a
+// time. It has 256 nibbles of memory, and on request copies the time in or
+// out of the first six: minutes of the day in 0-2 and days in 3-5.
+struct HuC3Rtc {
+    clock: Rc<dyn Clock>,
+    // The time the game last set, in minutes, and when on the clock it did.
+    // Everything since then is counted on top.
+    set_minutes: u64,
+    set_at: u64,
+    memory: [u8; 0x100],
+    addr: u8,
+    // The last command and what it came back with.
+    command: u8,
+    result: u8,
+}
+
+impl HuC3Rtc {
+    fn new(clock: Rc<dyn Clock>) -> HuC3Rtc {
+        let set_at = clock.now();
+        HuC3Rtc {
+            clock,
+            set_minutes: 0,
+            set_at,
+            memory: [0; 0x100],
+            addr: 0,
+            command: 0,
+            result: 0,
+        }
+    }
+
+    fn minutes(&self) -> u64 {
+        self.set_minutes + self.clock.now().saturating_sub(self.set_at) / 60
+    }
+
+    fn response(&self) -> u8 {
+        self.command << 4 | self.result
+    }
+
+    fn command(&mut self, val: u8) -> Option<Unsupported> {
+        let arg = val & 0x0F;
+        self.command = (val >> 4) & 0x07;
+        match self.command {
+            // Read and move on
+            0x1 => {
+                self.result = self.memory[self.addr as usize];
+                self.addr = self.addr.wrapping_add(1);
+            }
+            // Write and move on
+            0x3 => {
+                self.memory[self.addr as usize] = arg;
+                self.addr = self.addr.wrapping_add(1);
+            }
+            0x4 => self.addr = self.addr & 0xF0 | arg,
+            0x5 => self.addr = self.addr & 0x0F | arg << 4,
+            0x6 => match arg {
+                0x0 => self.copy_time_to_memory(),
+                0x1 => self.set_time_from_memory(),
+                // Status, which is always fine.
+                0x2 => self.result = 0x1,
+                0xE => return Some(Unsupported::Tone),
+                _ => (),
+            },
+            _ => (),
+        }
+        None
+    }
+
+    fn copy_time_to_memory(&mut self) {
+        let minutes = self.minutes();
+        let time = (minutes % MINUTES_PER_DAY) | (minutes / MINUTES_PER_DAY % 0x1000) << 12;
+        for i in 0..6 {
+            self.memory[i] = (time >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn set_time_from_memory(&mut self) {
+        let time = (0..6).fold(0, |time, i| time | (self.memory[i] as u64) << (i * 4));
+        self.set_minutes = (time & 0xFFF) + (time >> 12) * MINUTES_PER_DAY;
+        self.set_at = self.clock.now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+    use clock::ManualClock;
+
+    fn command(mbc: &mut HuC3, val: u8) -> u8 {
+        mbc.set_rom(0x0000, 0x0B);
+        mbc.set_ram(0xA000, val);
+        mbc.set_rom(0x0000, 0x0C);
+        mbc.fetch_ram(0xA000)
+    }
+
+    fn read_time(mbc: &mut HuC3) -> u64 {
+        command(mbc, 0x60);
+        command(mbc, 0x40);
+        command(mbc, 0x50);
+        (0..6).fold(0, |time, i| {
+            time | ((command(mbc, 0x10) & 0x0F) as u64) << (i * 4)
+        })
+    }
+
+    #[test]
+    fn ram_is_read_only_unless_mode_a() {
+        let mut mbc = HuC3::new(test_cartridge(0xFE, 0x05, 0x03));
+        mbc.set_ram(0xA000, 0x42);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x00);
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_ram(0xA000, 0x42);
+        mbc.set_rom(0x0000, 0x00);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+        mbc.set_rom(0x2000, 0x05);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 5);
+    }
+
+    #[test]
+    fn time_set_near_the_clock_epoch_is_kept() {
+        // A time later than the clock has counted up to.
+        let clock = Rc::new(ManualClock::new(0));
+        let cartridge = test_cartridge(0xFE, 0x05, 0x03).with_clock(clock.clone());
+        let mut mbc = HuC3::new(cartridge);
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        for nibble in &[0xA, 0x0, 0x0, 0x5, 0x0, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+        assert_eq!(read_time(&mut mbc), 5 << 12 | 10);
+        clock.advance(120);
+        assert_eq!(read_time(&mut mbc), 5 << 12 | 12);
+    }
+
+    #[test]
+    fn clock_counts_minutes_and_days() {
+        let clock = Rc::new(ManualClock::new(1000));
+        let cartridge = test_cartridge(0xFE, 0x05, 0x03).with_clock(clock.clone());
+        let mut mbc = HuC3::new(cartridge);
+        clock.advance(3 * 86400 + 90 * 60 + 59);
+        assert_eq!(read_time(&mut mbc), 3 << 12 | 90);
+        assert_eq!(command(&mut mbc, 0x62), 0x61);
+
+        // Setting it to 10 minutes into day 2.
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        for nibble in &[0xA, 0x0, 0x0, 0x2, 0x0, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+        clock.advance(60);
+        assert_eq!(read_time(&mut mbc), 2 << 12 | 11);
+
+        let save = mbc.save_data().unwrap();
+        let cartridge = test_cartridge(0xFE, 0x05, 0x03).with_clock(clock.clone());
+        let mut mbc = HuC3::new(cartridge);
+        mbc.load_save_data(&save);
+        clock.advance(60);
+        assert_eq!(read_time(&mut mbc), 2 << 12 | 12);
+    }
+}
@@ -0,0 +1,219 @@
+use cartridge::header::NINTENDO_LOGO;
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Up to 2MB of ROM and 32KB of RAM. A 5 bit register picks the ROM bank at
+// 0x4000, and a 2 bit one either picks the RAM bank or adds two more bits
+// to the ROM bank, depending on the banking mode.
+pub struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    ram_enabled: bool,
+    // 0x2000-0x3FFF. Writing 0 selects 1, since bank 0 is always at 0x0000.
+    bank1: u8,
+    // 0x4000-0x5FFF
+    bank2: u8,
+    // 0x6000-0x7FFF. In mode 1 the second register also applies to
+    // 0x0000-0x3FFF and to RAM.
+    advanced_mode: bool,
+    // MBC1M multicarts only connect 4 bits of the first register, so the
+    // second one starts at bit 4 instead of 5.
+    multicart: bool,
+}
+
+impl MBC1 {
+    pub fn new(cartridge: Cartridge) -> MBC1 {
+        let multicart = is_multicart(cartridge.rom());
+        MBC1 {
+            ram: super::cartridge_ram(&cartridge),
+            battery: cartridge.header().cartridge_type.battery,
+            rom: cartridge.into_rom(),
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_mode: false,
+            multicart,
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    // The bank at 0x0000, which is only ever moved in mode 1.
+    fn low_rom_bank(&self) -> usize {
+        if self.advanced_mode {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    fn high_rom_bank(&self) -> usize {
+        let bank1 = if self.multicart {
+            self.bank1 & 0x0F
+        } else {
+            self.bank1
+        };
+        (self.bank2 << self.bank2_shift() | bank1) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+// Multicarts are 1MB of 256KB games, each with its own header, so look for a
+// second copy of the logo where the second game would start.
+pub fn is_multicart(rom: &[u8]) -> bool {
+    let header = 0x10 * super::ROM_BANK_SIZE + 0x104;
+    rom.len() == 0x100000 && rom[header..header + NINTENDO_LOGO.len()] == NINTENDO_LOGO[..]
+}
+
+impl super::MBC for MBC1 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            self.low_rom_bank()
+        } else {
+            self.high_rom_bank()
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            // The check for 0 happens before the upper bits are added, so
+            // banks 0x20, 0x40 and 0x60 can't be selected here. It also
+            // looks at all 5 bits, even when the ROM is too small to need
+            // them.
+            0x2000..=0x3FFF => self.bank1 = (val & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = val & 0x03,
+            _ => self.advanced_mode = val & 0x01 != 0,
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match super::ram_index(&self.ram, self.ram_bank(), addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(index) = super::ram_index(&self.ram, self.ram_bank(), addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    #[test]
+    fn switches_rom_banks() {
+        // 256KB
+        let mut mbc = MBC1::new(test_cartridge(0x01, 0x03, 0x00));
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 1);
+        mbc.set_rom(0x2000, 0x05);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 5);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0);
+        // Bank numbers wrap around the 16 banks there are.
+        mbc.set_rom(0x2000, 0x1F);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x0F);
+    }
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let mut mbc = MBC1::new(test_cartridge(0x01, 0x06, 0x00));
+        mbc.set_rom(0x2000, 0x00);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 1);
+        // Only the low 5 bits are checked, so 0x20 isn't reachable at 0x4000.
+        mbc.set_rom(0x4000, 0x01);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x21);
+        // On a small ROM 0x10 still counts as nonzero, even though that bit
+        // isn't connected, so bank 0 shows up at 0x4000.
+        let mut mbc = MBC1::new(test_cartridge(0x01, 0x02, 0x00));
+        mbc.set_rom(0x2000, 0x10);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0);
+    }
+
+    #[test]
+    fn large_roms_use_the_upper_bits() {
+        // 2MB
+        let mut mbc = MBC1::new(test_cartridge(0x01, 0x06, 0x00));
+        mbc.set_rom(0x4000, 0x02);
+        mbc.set_rom(0x2000, 0x03);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x43);
+        // Mode 0 always has bank 0 at 0x0000...
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0);
+        // but mode 1 moves it too.
+        mbc.set_rom(0x6000, 0x01);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0x40);
+    }
+
+    #[test]
+    fn ram_needs_enabling_and_banks_in_mode_1() {
+        // 32KB of RAM
+        let mut mbc = MBC1::new(test_cartridge(0x03, 0x00, 0x03));
+        mbc.set_ram(0xA000, 0x42);
+        assert_eq!(mbc.fetch_ram(0xA000), 0xFF);
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_ram(0xA000, 0x42);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+
+        // The bank register only applies to RAM in mode 1.
+        mbc.set_rom(0x4000, 0x02);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+        mbc.set_rom(0x6000, 0x01);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x00);
+        mbc.set_ram(0xA000, 0x24);
+        mbc.set_rom(0x4000, 0x00);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+
+        mbc.set_rom(0x0000, 0x00);
+        assert_eq!(mbc.fetch_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicarts_are_detected_from_their_logos() {
+        let mut cartridge = test_cartridge(0x01, 0x05, 0x00).into_rom();
+        let header = 0x40000 + 0x104;
+        cartridge[header..header + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = MBC1::new(Cartridge::from_bytes(cartridge).unwrap());
+        assert!(mbc.multicart);
+        mbc.set_rom(0x4000, 0x01);
+        mbc.set_rom(0x2000, 0x12);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x12);
+        mbc.set_rom(0x6000, 0x01);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0x10);
+
+        let mbc = MBC1::new(test_cartridge(0x01, 0x05, 0x00));
+        assert!(!mbc.multicart);
+    }
+}
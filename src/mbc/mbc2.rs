@@ -0,0 +1,107 @@
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Up to 256KB of ROM, and 512 half bytes of RAM built into the chip. Both
+// registers are in 0x0000-0x3FFF, told apart by bit 8 of the address.
+pub struct MBC2 {
+    rom: Vec<u8>,
+    // Only the low nibble of each byte is used.
+    ram: [u8; 0x200],
+    battery: bool,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl MBC2 {
+    pub fn new(cartridge: Cartridge) -> MBC2 {
+        MBC2 {
+            battery: cartridge.header().cartridge_type.battery,
+            rom: cartridge.into_rom(),
+            ram: [0; 0x200],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl super::MBC for MBC2 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x3FFF if addr & 0x100 == 0 => self.ram_enabled = val & 0x0F == 0x0A,
+            // Bank 0 can't be selected here either.
+            0x0000..=0x3FFF => self.rom_bank = (val & 0x0F).max(1),
+            _ => (),
+        }
+    }
+
+    // The 512 bytes repeat through the whole of 0xA000-0xBFFF, and the top
+    // nibble isn't connected.
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        0xF0 | self.ram[(addr & 0x1FF) as usize]
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            self.ram[(addr & 0x1FF) as usize] = val & 0x0F;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+        for byte in self.ram.iter_mut() {
+            *byte &= 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    #[test]
+    fn address_bit_8_picks_the_register() {
+        // 256KB
+        let mut mbc = MBC2::new(test_cartridge(0x05, 0x03, 0x00));
+        mbc.set_rom(0x2100, 0x03);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 3);
+        // With bit 8 clear it's the RAM enable instead.
+        mbc.set_rom(0x2000, 0x05);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 3);
+        mbc.set_rom(0x0100, 0x00);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 1);
+        mbc.set_rom(0x3FFF, 0x1F);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x0F);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0);
+    }
+
+    #[test]
+    fn ram_is_half_bytes_echoed_through_the_area() {
+        let mut mbc = MBC2::new(test_cartridge(0x06, 0x00, 0x00));
+        mbc.set_ram(0xA000, 0x42);
+        assert_eq!(mbc.fetch_ram(0xA000), 0xFF);
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_ram(0xA000, 0x42);
+        assert_eq!(mbc.fetch_ram(0xA000), 0xF2);
+        assert_eq!(mbc.fetch_ram(0xA200), 0xF2);
+        assert_eq!(mbc.fetch_ram(0xBE00), 0xF2);
+        mbc.set_ram(0xB1FF, 0x0C);
+        assert_eq!(mbc.fetch_ram(0xA1FF), 0xFC);
+    }
+}
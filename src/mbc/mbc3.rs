@@ -0,0 +1,449 @@
+use std::rc::Rc;
+
+use cartridge::Cartridge;
+use clock::Clock;
+use error::EmulatorError;
+
+// Up to 2MB of ROM (4MB on MBC30) and 32KB of RAM (64KB on MBC30), plus a
+// real time clock on the cartridges that have one. Selecting 0x08-0x0C
+// instead of a RAM bank maps one of the clock's registers at 0xA000.
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // Covers the clock too.
+    ram_enabled: bool,
+    // 0x2000-0x3FFF. Writing 0 selects 1.
+    rom_bank: u8,
+    // 0x4000-0x5FFF. A RAM bank, or 0x08-0x0C for a clock register.
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+}
+
+impl MBC3 {
+    pub fn new(cartridge: Cartridge) -> MBC3 {
+        let rtc = if cartridge.header().cartridge_type.rtc {
+            Some(Rtc::new(cartridge.clock()))
+        } else {
+            None
+        };
+        MBC3 {
+            ram: super::cartridge_ram(&cartridge),
+            battery: cartridge.header().cartridge_type.battery,
+            rom: cartridge.into_rom(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc,
+        }
+    }
+
+    // MBC30 has one more bit for each bank, which only matters for games
+    // big enough to use it.
+    fn rom_bank_mask(&self) -> u8 {
+        if self.rom.len() > 0x200000 {
+            0xFF
+        } else {
+            0x7F
+        }
+    }
+}
+
+impl super::MBC for MBC3 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (val & self.rom_bank_mask()).max(1),
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match self.ram_bank {
+            0x00..=0x07 => match super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+                Some(index) => self.ram[index],
+                None => 0xFF,
+            },
+            0x08..=0x0C => match self.rtc {
+                Some(ref rtc) => rtc.fetch(self.ram_bank),
+                None => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match self.ram_bank {
+            0x00..=0x07 => {
+                if let Some(index) = super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+                    self.ram[index] = val;
+                }
+            }
+            0x08..=0x0C => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.set(self.ram_bank, val);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // The clock is saved after the RAM, the way most emulators do it.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        let mut data = self.ram.clone();
+        if let Some(ref rtc) = self.rtc {
+            rtc.save(&mut data);
+        }
+        Some(data)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+        if let Some(ref mut rtc) = self.rtc {
+            if data.len() >= self.ram.len() + RTC_SAVE_SIZE {
+                rtc.load(&data[self.ram.len()..]);
+            }
+        }
+    }
+}
+
+// Five little endian u32s each for the running and latched registers,
+// followed by a u64 timestamp.
+const RTC_SAVE_SIZE: usize = 48;
+
+// The clock's counters. They're only as wide as the hardware's, so values
+// written out of range count up to the top of the register and wrap to 0
+// without carrying.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    // 9 bits
+    days: u16,
+    halted: bool,
+    // Set when the day counter overflows, until it's written as 0.
+    day_carry: bool,
+}
+
+impl RtcRegisters {
+    fn fetch(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            _ => (self.day_carry as u8) << 7 | (self.halted as u8) << 6 | (self.days >> 8) as u8,
+        }
+    }
+
+    fn set(&mut self, register: u8, val: u8) {
+        match register {
+            0x08 => self.seconds = val & 0x3F,
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.days = self.days & 0x100 | val as u16,
+            _ => {
+                self.days = self.days & 0xFF | (val as u16 & 0x01) << 8;
+                self.halted = val & 0x40 != 0;
+                self.day_carry = val & 0x80 != 0;
+            }
+        }
+    }
+
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let days = self.days as u64 + days;
+        if days >= 512 {
+            self.day_carry = true;
+        }
+        self.days = (days % 512) as u16;
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // Out of range values have to be counted through one at a time until
+        // they wrap, after which the rest is plain arithmetic.
+        while seconds > 0 && !self.in_range() {
+            self.tick();
+            seconds -= 1;
+        }
+        let time = self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600;
+        let time = time + seconds;
+        self.seconds = (time % 60) as u8;
+        self.minutes = (time / 60 % 60) as u8;
+        self.hours = (time / 3600 % 24) as u8;
+        self.add_days(time / 86400);
+    }
+}
+
+struct Rtc {
+    clock: Rc<dyn Clock>,
+    live: RtcRegisters,
+    // What 0xA000 reads, as of the last latch.
+    latched: RtcRegisters,
+    // When live was last brought up to date.
+    updated_at: u64,
+    // Writing 0 and then 1 to 0x6000-0x7FFF latches the time.
+    latch_armed: bool,
+}
+
+impl Rtc {
+    fn new(clock: Rc<dyn Clock>) -> Rtc {
+        let updated_at = clock.now();
+        Rtc {
+            clock,
+            live: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            updated_at,
+            latch_armed: false,
+        }
+    }
+
+    // Counts the time since the last update, unless the clock is halted.
+    // A clock that goes backwards doesn't take any time away.
+    fn update(&mut self) {
+        let now = self.clock.now();
+        if !self.live.halted {
+            self.live.advance(now.saturating_sub(self.updated_at));
+        }
+        self.updated_at = now;
+    }
+
+    fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 0x01 {
+            self.update();
+            self.latched = self.live;
+        }
+        self.latch_armed = val == 0x00;
+    }
+
+    fn fetch(&self, register: u8) -> u8 {
+        self.latched.fetch(register)
+    }
+
+    fn save(&self, out: &mut Vec<u8>) {
+        for registers in &[self.live, self.latched] {
+            for register in 0x08..=0x0C {
+                out.extend_from_slice(&(registers.fetch(register) as u32).to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&self.updated_at.to_le_bytes());
+    }
+
+    // Time the game was off for is counted on the next update, as if the
+    // battery had kept the clock running.
+    fn load(&mut self, data: &[u8]) {
+        let byte = |index: usize| data[index * 4];
+        for register in 0x08..=0x0C {
+            let index = (register - 0x08) as usize;
+            self.live.set(register, byte(index));
+            self.latched.set(register, byte(index + 5));
+        }
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        self.updated_at = u64::from_le_bytes(timestamp);
+    }
+
+    // Writes go to the running clock, but show up in the latched registers
+    // straight away too.
+    fn set(&mut self, register: u8, val: u8) {
+        self.update();
+        self.live.set(register, val);
+        self.latched.set(register, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+    use clock::ManualClock;
+
+    fn mbc3_with_clock(clock: &Rc<ManualClock>) -> MBC3 {
+        // MBC3+TIMER+RAM+BATTERY with 32KB of RAM
+        let cartridge = test_cartridge(0x10, 0x06, 0x03).with_clock(clock.clone());
+        let mut mbc = MBC3::new(cartridge);
+        mbc.set_rom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn latch(mbc: &mut MBC3) {
+        mbc.set_rom(0x6000, 0x00);
+        mbc.set_rom(0x6000, 0x01);
+    }
+
+    fn read_rtc(mbc: &mut MBC3, register: u8) -> u8 {
+        mbc.set_rom(0x4000, register);
+        mbc.fetch_ram(0xA000)
+    }
+
+    #[test]
+    fn switches_rom_and_ram_banks() {
+        let mut mbc = MBC3::new(test_cartridge(0x13, 0x06, 0x03));
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 1);
+        // Unlike MBC1 all 7 bits are compared with 0.
+        mbc.set_rom(0x2000, 0x40);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x40);
+        mbc.set_rom(0x2000, 0x00);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 1);
+
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_rom(0x4000, 0x02);
+        mbc.set_ram(0xA000, 0x42);
+        mbc.set_rom(0x4000, 0x01);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x00);
+        mbc.set_rom(0x4000, 0x02);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+        // No clock on this one.
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0xFF);
+    }
+
+    #[test]
+    fn latching_snapshots_the_time() {
+        let clock = Rc::new(ManualClock::new(1000));
+        let mut mbc = mbc3_with_clock(&clock);
+        clock.advance(2 * 86400 + 3 * 3600 + 4 * 60 + 5);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 5);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 4);
+        assert_eq!(read_rtc(&mut mbc, 0x0A), 3);
+        assert_eq!(read_rtc(&mut mbc, 0x0B), 2);
+        assert_eq!(read_rtc(&mut mbc, 0x0C), 0);
+
+        // Reads stay put until the next latch, which needs the 0 first.
+        clock.advance(10);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 5);
+        mbc.set_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 5);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 15);
+    }
+
+    #[test]
+    fn halting_stops_the_clock() {
+        let clock = Rc::new(ManualClock::new(0));
+        let mut mbc = mbc3_with_clock(&clock);
+        clock.advance(30);
+        mbc.set_rom(0x4000, 0x0C);
+        mbc.set_ram(0xA000, 0x40);
+        clock.advance(100);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 30);
+        assert_eq!(read_rtc(&mut mbc, 0x0C), 0x40);
+
+        // Setting the time while halted, then starting it again.
+        mbc.set_rom(0x4000, 0x09);
+        mbc.set_ram(0xA000, 59);
+        mbc.set_rom(0x4000, 0x0C);
+        mbc.set_ram(0xA000, 0x00);
+        clock.advance(30);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 0);
+        assert_eq!(read_rtc(&mut mbc, 0x0A), 1);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_the_carry() {
+        let clock = Rc::new(ManualClock::new(0));
+        let mut mbc = mbc3_with_clock(&clock);
+        mbc.set_rom(0x4000, 0x0B);
+        mbc.set_ram(0xA000, 0xFF);
+        mbc.set_rom(0x4000, 0x0C);
+        mbc.set_ram(0xA000, 0x01);
+        clock.advance(86400);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x0B), 0);
+        assert_eq!(read_rtc(&mut mbc, 0x0C), 0x80);
+        // The carry stays until it's cleared.
+        clock.advance(86400);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x0B), 1);
+        assert_eq!(read_rtc(&mut mbc, 0x0C), 0x80);
+        mbc.set_ram(0xA000, 0x00);
+        assert_eq!(read_rtc(&mut mbc, 0x0C), 0x00);
+    }
+
+    #[test]
+    fn saves_keep_the_clock_running() {
+        let clock = Rc::new(ManualClock::new(500));
+        let mut mbc = mbc3_with_clock(&clock);
+        mbc.set_ram(0xA000, 0x42);
+        clock.advance(90);
+        latch(&mut mbc);
+        let save = mbc.save_data().unwrap();
+        assert_eq!(save.len(), 0x8000 + RTC_SAVE_SIZE);
+
+        clock.advance(3600);
+        let mut mbc = mbc3_with_clock(&clock);
+        mbc.load_save_data(&save);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 1);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 30);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 1);
+        assert_eq!(read_rtc(&mut mbc, 0x0A), 1);
+    }
+
+    #[test]
+    fn out_of_range_values_wrap_without_carrying() {
+        let mut registers = RtcRegisters {
+            seconds: 62,
+            minutes: 59,
+            ..RtcRegisters::default()
+        };
+        registers.advance(3);
+        assert_eq!((registers.seconds, registers.minutes), (1, 59));
+        registers.advance(59);
+        assert_eq!(
+            (registers.seconds, registers.minutes, registers.hours),
+            (0, 0, 1)
+        );
+    }
+}
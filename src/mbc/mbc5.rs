@@ -0,0 +1,147 @@
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Up to 8MB of ROM and 128KB of RAM. The ROM bank is 9 bits split across
+// two registers, and since bank 0 is never swapped for 1 it can be mapped
+// at 0x4000 too. On the rumble cartridges bit 3 of the RAM bank drives the
+// motor instead.
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    ram_enabled: bool,
+    // 0x2000-0x2FFF has the low 8 bits and 0x3000-0x3FFF the 9th.
+    rom_bank: u16,
+    // 0x4000-0x5FFF
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(cartridge: Cartridge) -> MBC5 {
+        MBC5 {
+            ram: super::cartridge_ram(&cartridge),
+            battery: cartridge.header().cartridge_type.battery,
+            has_rumble: cartridge.header().cartridge_type.rumble,
+            rom: cartridge.into_rom(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble: false,
+        }
+    }
+}
+
+impl super::MBC for MBC5 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            // All 8 bits are checked here, not just the low 4.
+            0x0000..=0x1FFF => self.ram_enabled = val == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = self.rom_bank & 0x100 | val as u16,
+            0x3000..=0x3FFF => self.rom_bank = self.rom_bank & 0xFF | (val as u16 & 0x01) << 8,
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = val & 0x08 != 0;
+                    self.ram_bank = val & 0x07;
+                } else {
+                    self.ram_bank = val & 0x0F;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(index) = super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    #[test]
+    fn nine_bit_rom_banks() {
+        // 8MB
+        let mut mbc = MBC5::new(test_cartridge(0x19, 0x08, 0x00));
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 1);
+        mbc.set_rom(0x2000, 0x00);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0);
+        mbc.set_rom(0x3000, 0x01);
+        mbc.set_rom(0x2000, 0x23);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x23);
+        assert_eq!(mbc.fetch_rom(0x4001).unwrap(), 0x01);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0);
+    }
+
+    #[test]
+    fn sixteen_ram_banks() {
+        // 128KB of RAM
+        let mut mbc = MBC5::new(test_cartridge(0x1B, 0x01, 0x04));
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_rom(0x4000, 0x0F);
+        mbc.set_ram(0xBFFF, 0x42);
+        mbc.set_rom(0x4000, 0x07);
+        assert_eq!(mbc.fetch_ram(0xBFFF), 0x00);
+        mbc.set_rom(0x4000, 0x0F);
+        assert_eq!(mbc.fetch_ram(0xBFFF), 0x42);
+        // 0x1A has the right low bits, but isn't 0x0A.
+        mbc.set_rom(0x0000, 0x1A);
+        assert_eq!(mbc.fetch_ram(0xBFFF), 0xFF);
+    }
+
+    #[test]
+    fn rumble_takes_bit_3_of_the_ram_bank() {
+        // MBC5+RUMBLE+RAM with 32KB of RAM
+        let mut mbc = MBC5::new(test_cartridge(0x1D, 0x01, 0x03));
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_rom(0x4000, 0x09);
+        assert!(mbc.rumble());
+        mbc.set_ram(0xA000, 0x42);
+        mbc.set_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.fetch_ram(0xA000), 0x42);
+
+        let mut mbc = MBC5::new(test_cartridge(0x1B, 0x01, 0x04));
+        mbc.set_rom(0x4000, 0x08);
+        assert!(!mbc.rumble());
+    }
+}
@@ -0,0 +1,419 @@
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Level, on either axis.
+const TILT_CENTER: f32 = 0x81D0 as f32;
+// How far the reading moves for 1g.
+const TILT_PER_G: f32 = 0x70 as f32;
+
+// Up to 2MB of ROM, with a two axis accelerometer and a 93LC56 serial
+// EEPROM in place of RAM. Both sit in 0xA000-0xAFFF behind two enables,
+// with bits 4-7 of the address picking the register.
+pub struct MBC7 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    // 0x0A to 0x0000-0x1FFF and then 0x40 to 0x4000-0x5FFF.
+    ram_enabled: bool,
+    registers_enabled: bool,
+    // In g, as last set by the host.
+    tilt: (f32, f32),
+    // What the game reads, as of the last latch.
+    latched: (u16, u16),
+    // Writing 0x55 to Ax0x and then 0xAA to Ax1x latches the tilt.
+    latch_armed: bool,
+    eeprom: Eeprom,
+}
+
+impl MBC7 {
+    pub fn new(cartridge: Cartridge) -> MBC7 {
+        MBC7 {
+            rom: cartridge.into_rom(),
+            rom_bank: 1,
+            ram_enabled: false,
+            registers_enabled: false,
+            tilt: (0.0, 0.0),
+            latched: (0x8000, 0x8000),
+            latch_armed: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+}
+
+fn tilt_reading(tilt: f32) -> u16 {
+    (TILT_CENTER + tilt * TILT_PER_G)
+        .round()
+        .max(0.0)
+        .min(0xFFFF as f32) as u16
+}
+
+impl super::MBC for MBC7 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = val == 0x0A;
+                if !self.ram_enabled {
+                    self.registers_enabled = false;
+                }
+            }
+            0x2000..=0x3FFF => self.rom_bank = val & 0x7F,
+            0x4000..=0x5FFF => self.registers_enabled = self.ram_enabled && val == 0x40,
+            _ => (),
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if !self.registers_enabled || addr >= 0xB000 {
+            return 0xFF;
+        }
+        match (addr >> 4) & 0x0F {
+            0x2 => self.latched.0 as u8,
+            0x3 => (self.latched.0 >> 8) as u8,
+            0x4 => self.latched.1 as u8,
+            0x5 => (self.latched.1 >> 8) as u8,
+            // There's no Z axis, but it has a register anyway.
+            0x6 => 0x00,
+            0x8 => self.eeprom.fetch(),
+            _ => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if !self.registers_enabled || addr >= 0xB000 {
+            return;
+        }
+        match (addr >> 4) & 0x0F {
+            0x0 if val == 0x55 => {
+                self.latched = (0x8000, 0x8000);
+                self.latch_armed = true;
+            }
+            0x1 if val == 0xAA && self.latch_armed => {
+                self.latched = (tilt_reading(self.tilt.0), tilt_reading(self.tilt.1));
+                self.latch_armed = false;
+            }
+            0x8 => self.eeprom.set(val),
+            _ => (),
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.eeprom.data.to_vec())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.eeprom.data, data);
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EepromState {
+    // Waiting for a start bit.
+    Idle,
+    // The 2 bit opcode and 8 bit address after the start bit.
+    Command {
+        bits: u16,
+        count: u8,
+    },
+    // Shifting out words, one after another for as long as the clock runs.
+    Reading {
+        addr: u8,
+        count: u8,
+    },
+    // Shifting in a word for one address, or every one.
+    Writing {
+        addr: Option<u8>,
+        bits: u16,
+        count: u8,
+    },
+}
+
+// 128 16 bit words, talked to a bit at a time through Ax8x: bit 7 is chip
+// select, bit 6 the clock, bit 1 data in and bit 0 data out. Bits move on
+// the rising edge of the clock. Writes finish straight away, so data out
+// always says it's ready afterwards.
+struct Eeprom {
+    // Each word is stored little endian.
+    data: [u8; 0x100],
+    chip_select: bool,
+    clock: bool,
+    data_in: bool,
+    data_out: bool,
+    // Everything but reads needs EWEN first.
+    write_enabled: bool,
+    state: EepromState,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: [0xFF; 0x100],
+            chip_select: false,
+            clock: false,
+            data_in: false,
+            data_out: true,
+            write_enabled: false,
+            state: EepromState::Idle,
+        }
+    }
+
+    fn word(&self, addr: u8) -> u16 {
+        let index = addr as usize * 2;
+        self.data[index] as u16 | (self.data[index + 1] as u16) << 8
+    }
+
+    fn set_word(&mut self, addr: u8, val: u16) {
+        if self.write_enabled {
+            let index = addr as usize * 2;
+            self.data[index] = val as u8;
+            self.data[index + 1] = (val >> 8) as u8;
+        }
+    }
+
+    fn fetch(&self) -> u8 {
+        (self.chip_select as u8) << 7
+            | (self.clock as u8) << 6
+            | (self.data_in as u8) << 1
+            | self.data_out as u8
+    }
+
+    fn set(&mut self, val: u8) {
+        let rising = !self.clock && val & 0x40 != 0;
+        self.chip_select = val & 0x80 != 0;
+        self.clock = val & 0x40 != 0;
+        self.data_in = val & 0x02 != 0;
+        if !self.chip_select {
+            // Dropping chip select abandons whatever was going on.
+            self.state = EepromState::Idle;
+            self.data_out = true;
+        } else if rising {
+            self.shift();
+        }
+    }
+
+    fn shift(&mut self) {
+        let bit = self.data_in as u16;
+        self.state = match self.state {
+            EepromState::Idle if bit == 1 => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } => {
+                let bits = bits << 1 | bit;
+                if count + 1 == 10 {
+                    self.command(bits)
+                } else {
+                    EepromState::Command {
+                        bits,
+                        count: count + 1,
+                    }
+                }
+            }
+            EepromState::Reading { addr, count } => {
+                self.data_out = self.word(addr) & 0x8000 >> count != 0;
+                if count == 15 {
+                    EepromState::Reading {
+                        addr: (addr + 1) & 0x7F,
+                        count: 0,
+                    }
+                } else {
+                    EepromState::Reading {
+                        addr,
+                        count: count + 1,
+                    }
+                }
+            }
+            EepromState::Writing { addr, bits, count } => {
+                let bits = bits << 1 | bit;
+                if count + 1 < 16 {
+                    EepromState::Writing {
+                        addr,
+                        bits,
+                        count: count + 1,
+                    }
+                } else {
+                    match addr {
+                        Some(addr) => self.set_word(addr, bits),
+                        None => (0..0x80).for_each(|addr| self.set_word(addr, bits)),
+                    }
+                    self.data_out = true;
+                    EepromState::Idle
+                }
+            }
+        };
+    }
+
+    // The top bit of the address isn't used with 128 words.
+    fn command(&mut self, bits: u16) -> EepromState {
+        let addr = (bits & 0x7F) as u8;
+        match bits >> 8 {
+            // READ, which starts with a dummy 0.
+            0b10 => {
+                self.data_out = false;
+                EepromState::Reading { addr, count: 0 }
+            }
+            // WRITE
+            0b01 => EepromState::Writing {
+                addr: Some(addr),
+                bits: 0,
+                count: 0,
+            },
+            // ERASE
+            0b11 => {
+                self.set_word(addr, 0xFFFF);
+                EepromState::Idle
+            }
+            _ => match (bits >> 6) & 0x03 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    EepromState::Idle
+                }
+                // WRAL
+                0b01 => EepromState::Writing {
+                    addr: None,
+                    bits: 0,
+                    count: 0,
+                },
+                // ERAL
+                0b10 => {
+                    (0..0x80).for_each(|addr| self.set_word(addr, 0xFFFF));
+                    EepromState::Idle
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    EepromState::Idle
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    fn enabled_mbc7() -> MBC7 {
+        let mut mbc = MBC7::new(test_cartridge(0x22, 0x06, 0x00));
+        mbc.set_rom(0x0000, 0x0A);
+        mbc.set_rom(0x4000, 0x40);
+        mbc
+    }
+
+    // Clocks bits in with chip select held, and returns what data out was
+    // after each one.
+    fn send(mbc: &mut MBC7, bits: &[u8]) -> Vec<u8> {
+        bits.iter()
+            .map(|&bit| {
+                mbc.set_ram(0xA080, 0x80 | bit << 1);
+                mbc.set_ram(0xA080, 0xC0 | bit << 1);
+                mbc.fetch_ram(0xA080) & 0x01
+            })
+            .collect()
+    }
+
+    // Start bit, opcode and the 8 address bits.
+    fn command(opcode: u8, addr: u8) -> Vec<u8> {
+        let mut bits = vec![1, opcode >> 1, opcode & 1];
+        bits.extend((0..8).rev().map(|i| (addr >> i) & 1));
+        bits
+    }
+
+    fn end(mbc: &mut MBC7) {
+        mbc.set_ram(0xA080, 0x00);
+    }
+
+    fn word_bits(word: u16) -> Vec<u8> {
+        (0..16).rev().map(|i| (word >> i) as u8 & 1).collect()
+    }
+
+    fn read_word(mbc: &mut MBC7, addr: u8) -> u16 {
+        let out = send(mbc, &command(0b10, addr));
+        assert_eq!(out[10], 0);
+        let out = send(mbc, &[0; 16]);
+        end(mbc);
+        out.iter().fold(0, |word, &bit| word << 1 | bit as u16)
+    }
+
+    #[test]
+    fn eeprom_needs_writes_enabled() {
+        let mut mbc = enabled_mbc7();
+        let mut bits = command(0b01, 0x05);
+        bits.extend(word_bits(0x1234));
+        send(&mut mbc, &bits);
+        end(&mut mbc);
+        assert_eq!(read_word(&mut mbc, 0x05), 0xFFFF);
+
+        // EWEN
+        send(&mut mbc, &command(0b00, 0xC0));
+        end(&mut mbc);
+        send(&mut mbc, &bits);
+        end(&mut mbc);
+        assert_eq!(read_word(&mut mbc, 0x05), 0x1234);
+        assert_eq!(mbc.save_data().unwrap()[10..12], [0x34, 0x12]);
+
+        // ERASE
+        send(&mut mbc, &command(0b11, 0x05));
+        end(&mut mbc);
+        assert_eq!(read_word(&mut mbc, 0x05), 0xFFFF);
+    }
+
+    #[test]
+    fn eeprom_write_all_and_sequential_reads() {
+        let mut mbc = enabled_mbc7();
+        send(&mut mbc, &command(0b00, 0xC0));
+        end(&mut mbc);
+        // WRAL
+        let mut bits = command(0b00, 0x40);
+        bits.extend(word_bits(0xA55A));
+        send(&mut mbc, &bits);
+        end(&mut mbc);
+
+        // Reading past the end of a word carries on with the next one.
+        send(&mut mbc, &command(0b10, 0x7F));
+        let out = send(&mut mbc, &[0; 32]);
+        end(&mut mbc);
+        let word = |bits: &[u8]| bits.iter().fold(0, |word, &bit| word << 1 | bit as u16);
+        assert_eq!(word(&out[..16]), 0xA55A);
+        assert_eq!(word(&out[16..]), 0xA55A);
+
+        let mut loaded = enabled_mbc7();
+        loaded.load_save_data(&mbc.save_data().unwrap());
+        assert_eq!(read_word(&mut loaded, 0x00), 0xA55A);
+    }
+
+    #[test]
+    fn tilt_is_latched() {
+        let mut mbc = enabled_mbc7();
+        mbc.set_tilt(1.0, -0.5);
+        assert_eq!(mbc.fetch_ram(0xA020), 0x00);
+        assert_eq!(mbc.fetch_ram(0xA030), 0x80);
+        mbc.set_ram(0xA010, 0xAA);
+        assert_eq!(mbc.fetch_ram(0xA030), 0x80);
+
+        mbc.set_ram(0xA000, 0x55);
+        mbc.set_ram(0xA010, 0xAA);
+        assert_eq!(mbc.fetch_ram(0xA020), 0x40);
+        assert_eq!(mbc.fetch_ram(0xA030), 0x82);
+        assert_eq!(mbc.fetch_ram(0xA040), 0x98);
+        assert_eq!(mbc.fetch_ram(0xA050), 0x81);
+        // Later tilting needs another latch to show up.
+        mbc.set_tilt(0.0, 0.0);
+        assert_eq!(mbc.fetch_ram(0xA020), 0x40);
+
+        // Both enables are needed.
+        mbc.set_rom(0x4000, 0x00);
+        assert_eq!(mbc.fetch_ram(0xA020), 0xFF);
+    }
+}
@@ -0,0 +1,239 @@
+use cartridge::header::{CartridgeHeader, Mapper};
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Multicarts with a menu in the last 32KB of ROM. Until the menu maps a
+// game in, that's all that shows up. The menu sets where the game's ROM and
+// RAM start and which bank bits the game can change, and then the game sees
+// an MBC1 confined to its part of the cartridge.
+pub struct MMM01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // Set by bit 6 of 0x0000-0x1FFF, and only undone by a reset.
+    mapped: bool,
+    ram_enabled: bool,
+    // 0x2000-0x3FFF, the game's MBC1 bank register, plus two more bits that
+    // only the menu can set.
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    // 0x4000-0x5FFF, the game's RAM bank, and more bits for the menu.
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    rom_bank_high: u8,
+    // Bits 1-4 of rom_bank_low the game can't change, from 0x6000-0x7FFF.
+    rom_bank_mask: u8,
+    // 0x6000-0x7FFF, MBC1's banking mode.
+    advanced_mode: bool,
+    // The menu can stop the game from changing modes too.
+    mode_locked: bool,
+}
+
+// The header that counts is the menu's. The one at 0x100 is usually the
+// first game's. Any ROM could have one of MMM01's type bytes there by
+// chance, so only a header with the logo and a good checksum counts.
+fn menu_header(rom: &[u8]) -> Option<CartridgeHeader> {
+    if rom.len() < 0x8000 {
+        return None;
+    }
+    CartridgeHeader::parse(&rom[rom.len() - 0x8000..])
+        .ok()
+        .filter(|header| header.logo_valid && header.header_checksum_valid())
+}
+
+pub fn is_mmm01(rom: &[u8], header: &CartridgeHeader) -> bool {
+    header.cartridge_type.mapper == Mapper::MMM01
+        || menu_header(rom).is_some_and(|header| header.cartridge_type.mapper == Mapper::MMM01)
+}
+
+impl MMM01 {
+    pub fn new(cartridge: Cartridge) -> MMM01 {
+        let header = menu_header(cartridge.rom())
+            .filter(|header| header.cartridge_type.mapper == Mapper::MMM01)
+            .unwrap_or_else(|| cartridge.header().clone());
+        MMM01 {
+            ram: vec![0; header.ram_size.unwrap_or(0)],
+            battery: header.cartridge_type.battery,
+            rom: cartridge.into_rom(),
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            advanced_mode: false,
+            mode_locked: false,
+        }
+    }
+
+    fn rom_bank_base(&self) -> usize {
+        (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.advanced_mode {
+            self.ram_bank_low
+        } else {
+            0
+        };
+        (self.ram_bank_high << 2 | low) as usize
+    }
+}
+
+impl super::MBC for MMM01 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        // Unmapped, every bank bit is high, which is the last 32KB.
+        if !self.mapped {
+            let bank = if addr < 0x4000 { 0x1FE } else { 0x1FF };
+            return Ok(super::rom_byte(&self.rom, bank, addr));
+        }
+        let low = if addr < 0x4000 {
+            self.rom_bank_low & self.rom_bank_mask
+        } else if self.rom_bank_low & !self.rom_bank_mask == 0 {
+            // The same check for 0 as MBC1, on the bits the game controls.
+            self.rom_bank_low | 0x01
+        } else {
+            self.rom_bank_low
+        };
+        Ok(super::rom_byte(
+            &self.rom,
+            self.rom_bank_base() | low as usize,
+            addr,
+        ))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = val & 0x0F == 0x0A;
+                self.mapped |= val & 0x40 != 0;
+            }
+            0x2000..=0x3FFF if self.mapped => {
+                self.rom_bank_low =
+                    self.rom_bank_low & self.rom_bank_mask | val & 0x1F & !self.rom_bank_mask;
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_low = val & 0x1F;
+                self.rom_bank_mid = (val >> 5) & 0x03;
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = val & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (val >> 2) & 0x03;
+                    self.rom_bank_high = (val >> 4) & 0x03;
+                    self.mode_locked = val & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mapped || !self.mode_locked {
+                    self.advanced_mode = val & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (val >> 1) & 0x1E;
+                }
+            }
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match super::ram_index(&self.ram, self.ram_bank(), addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(index) = super::ram_index(&self.ram, self.ram_bank(), addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+    use cartridge::header::NINTENDO_LOGO;
+
+    // 1MB, with an MBC1 game header first and the MMM01 menu's at the end.
+    fn multicart() -> Cartridge {
+        let mut rom = test_cartridge(0x01, 0x05, 0x00).into_rom();
+        let menu = rom.len() - 0x8000;
+        rom[menu + 0x104..menu + 0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[menu + 0x147] = 0x0D;
+        rom[menu + 0x149] = 0x03;
+        let checksum = CartridgeHeader::parse(&rom[menu..])
+            .unwrap()
+            .computed_header_checksum;
+        rom[menu + 0x14D] = checksum;
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    fn the_menu_comes_first() {
+        let cartridge = multicart();
+        assert!(is_mmm01(cartridge.rom(), cartridge.header()));
+        let mbc = MMM01::new(cartridge);
+        assert_eq!(mbc.ram.len(), 0x8000);
+        assert!(mbc.battery);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0x3E);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x3F);
+        let cartridge = test_cartridge(0x01, 0x05, 0x00);
+        assert!(!is_mmm01(cartridge.rom(), cartridge.header()));
+    }
+
+    #[test]
+    fn stray_type_bytes_are_not_menus() {
+        // An MBC1 game that happens to have 0x0B where a menu header would
+        // be, without the logo or checksum.
+        let mut rom = test_cartridge(0x01, 0x05, 0x00).into_rom();
+        let menu = rom.len() - 0x8000;
+        rom[menu + 0x147] = 0x0B;
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert!(!is_mmm01(cartridge.rom(), cartridge.header()));
+        assert_eq!(
+            super::super::emulated_mapper(cartridge.rom(), cartridge.header()),
+            Mapper::MBC1
+        );
+
+        // The logo alone isn't enough either.
+        rom[menu + 0x104..menu + 0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[menu + 0x14D] = 0x00;
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert!(!is_mmm01(cartridge.rom(), cartridge.header()));
+    }
+
+    #[test]
+    fn games_are_confined_to_their_banks() {
+        let mut mbc = MMM01::new(multicart());
+        // Start at bank 0x20, and let the game have 4 banks.
+        mbc.set_rom(0x2000, 0x20);
+        mbc.set_rom(0x6000, 0x38);
+        mbc.set_rom(0x0000, 0x40);
+        assert_eq!(mbc.fetch_rom(0x0000).unwrap(), 0x20);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x21);
+        mbc.set_rom(0x2000, 0x03);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x23);
+        // The bits above the game's are fixed now.
+        mbc.set_rom(0x2000, 0x1F);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x23);
+        mbc.set_rom(0x4000, 0x30);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x23);
+    }
+}
@@ -0,0 +1,170 @@
+// Memory bank controllers: the chips on the cartridge that map its ROM and
+// RAM into 0x0000-0x7FFF and 0xA000-0xBFFF. Writes to the ROM area go to
+// their registers instead.
+extern crate log;
+
+use self::log::warn;
+
+use cartridge::header::{CartridgeHeader, Mapper};
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+pub mod huc1;
+pub mod huc3;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod mbc7;
+pub mod mmm01;
+pub mod pocket_camera;
+pub mod rom_only;
+pub mod tama5;
+
+use self::huc1::HuC1;
+use self::huc3::HuC3;
+use self::mbc1::MBC1;
+use self::mbc2::MBC2;
+use self::mbc3::MBC3;
+use self::mbc5::MBC5;
+use self::mbc7::MBC7;
+use self::mmm01::MMM01;
+use self::pocket_camera::PocketCamera;
+use self::rom_only::RomOnly;
+use self::tama5::TAMA5;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+pub trait MBC {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError>;
+    fn set_rom(&mut self, addr: u16, val: u8);
+    fn fetch_ram(&self, addr: u16) -> u8;
+    fn set_ram(&mut self, addr: u16, val: u8);
+
+    // What to keep between runs on battery backed cartridges, None when
+    // there's nothing to keep.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    // Restores what save_data returned on an earlier run.
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    // Whether a rumble motor on the cartridge is running.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    // How far the cartridge is tilted, for the ones with an accelerometer.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+}
+
+// What the cartridge is emulated as, which isn't always what its type byte
+// says: MMM01 multicarts have their own header at the end of the ROM, and
+// mappers that aren't supported yet run as if there were no mapper.
+pub fn emulated_mapper(rom: &[u8], header: &CartridgeHeader) -> Mapper {
+    if mmm01::is_mmm01(rom, header) {
+        return Mapper::MMM01;
+    }
+    match header.cartridge_type.mapper {
+        Mapper::MBC6 | Mapper::Unknown(_) => Mapper::RomOnly,
+        mapper => mapper,
+    }
+}
+
+// Multicarts that don't say so in their header.
+pub fn is_multicart(rom: &[u8], header: &CartridgeHeader) -> bool {
+    match emulated_mapper(rom, header) {
+        Mapper::MMM01 => true,
+        Mapper::MBC1 => mbc1::is_multicart(rom),
+        _ => false,
+    }
+}
+
+// RAM that's part of the mapper, which the header doesn't count.
+pub fn built_in_ram(mapper: Mapper) -> Option<&'static str> {
+    match mapper {
+        Mapper::MBC2 => Some("512 half bytes"),
+        Mapper::MBC7 => Some("256 byte EEPROM"),
+        Mapper::TAMA5 => Some("32 bytes"),
+        _ => None,
+    }
+}
+
+// Picks the controller the cartridge needs.
+pub fn from_cartridge(cartridge: Cartridge) -> Box<dyn MBC> {
+    match emulated_mapper(cartridge.rom(), cartridge.header()) {
+        Mapper::MBC1 => Box::new(MBC1::new(cartridge)),
+        Mapper::MBC2 => Box::new(MBC2::new(cartridge)),
+        Mapper::MBC3 => Box::new(MBC3::new(cartridge)),
+        Mapper::MBC5 => Box::new(MBC5::new(cartridge)),
+        Mapper::MBC7 => Box::new(MBC7::new(cartridge)),
+        Mapper::MMM01 => Box::new(MMM01::new(cartridge)),
+        Mapper::HuC1 => Box::new(HuC1::new(cartridge)),
+        Mapper::HuC3 => Box::new(HuC3::new(cartridge)),
+        Mapper::TAMA5 => Box::new(TAMA5::new(cartridge)),
+        Mapper::PocketCamera => Box::new(PocketCamera::new(cartridge)),
+        _ => {
+            let mapper = cartridge.header().cartridge_type.mapper;
+            if mapper != Mapper::RomOnly {
+                warn!(
+                    "{} isn't supported yet, running without bank switching",
+                    mapper
+                );
+            }
+            Box::new(RomOnly::new(cartridge))
+        }
+    }
+}
+
+// A byte from a 16KB ROM bank. Bank numbers past the end of the ROM wrap,
+// since the unused high bits of the bank register aren't connected.
+fn rom_byte(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    rom[(bank * ROM_BANK_SIZE + (addr as usize & 0x3FFF)) % rom.len()]
+}
+
+// Where an address in an 8KB RAM bank ends up in the cartridge's RAM, which
+// wraps the same way as the ROM. None when there's no RAM at all.
+fn ram_index(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (addr as usize & 0x1FFF)) % ram.len())
+}
+
+// External RAM as big as the header says.
+fn cartridge_ram(cartridge: &Cartridge) -> Vec<u8> {
+    vec![0; cartridge.header().ram_size.unwrap_or(0)]
+}
+
+// The RAM as a save, when a battery keeps it.
+fn battery_ram(battery: bool, ram: &[u8]) -> Option<Vec<u8>> {
+    if battery && !ram.is_empty() {
+        Some(ram.to_vec())
+    } else {
+        None
+    }
+}
+
+// Copies a save into RAM. Saves from other emulators can be a different
+// size, so only as much as fits is used.
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+// A cartridge with the given type and size codes, where the first byte of
+// each ROM bank is the bank number.
+#[cfg(test)]
+pub fn test_cartridge(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Cartridge {
+    let mut rom = vec![0; 0x8000 << rom_size_code];
+    for bank in 0..rom.len() / ROM_BANK_SIZE {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+        rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+    }
+    rom[0x147] = cartridge_type;
+    rom[0x148] = rom_size_code;
+    rom[0x149] = ram_size_code;
+    Cartridge::from_bytes(rom).unwrap()
+}
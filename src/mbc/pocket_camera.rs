@@ -0,0 +1,322 @@
+extern crate log;
+
+use self::log::warn;
+use std::rc::Rc;
+
+use camera::{ImageSource, CAMERA_HEIGHT, CAMERA_WIDTH};
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Where a capture goes in RAM bank 0, as 16 by 14 tiles.
+const PHOTO_ADDR: usize = 0x100;
+const PHOTO_SIZE: usize = CAMERA_WIDTH * CAMERA_HEIGHT / 4;
+// The 4x4 dithering matrix at 0xA006, with three thresholds per pixel.
+const MATRIX_REGISTER: usize = 0x06;
+const REGISTER_COUNT: usize = 0x36;
+// How strongly edges are enhanced, from bits 4-6 of 0xA004.
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+// The Game Boy Camera's mapper, with up to 1MB of ROM, 128KB of RAM, and
+// the registers of its M64282FP sensor mapped at 0xA000 when bit 4 of the
+// RAM bank is set. Captures finish straight away.
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // Only writes need RAM enabled.
+    ram_enabled: bool,
+    // 0x2000-0x3FFF. Bank 0 can be selected.
+    rom_bank: u8,
+    // 0x4000-0x5FFF
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    camera: Rc<dyn ImageSource>,
+}
+
+// Whether there's room in RAM for a photo, which headers with less than the
+// 128KB the real cartridge has might not leave.
+fn photo_fits(ram: &[u8]) -> bool {
+    ram.len() >= PHOTO_ADDR + PHOTO_SIZE
+}
+
+impl PocketCamera {
+    pub fn new(cartridge: Cartridge) -> PocketCamera {
+        let ram = super::cartridge_ram(&cartridge);
+        if !photo_fits(&ram) {
+            warn!(
+                "The Pocket Camera's {} bytes of RAM can't hold a photo, captures will be skipped",
+                ram.len()
+            );
+        }
+        PocketCamera {
+            ram,
+            battery: cartridge.header().cartridge_type.battery,
+            camera: cartridge.camera(),
+            rom: cartridge.into_rom(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        self.ram_bank & 0x10 != 0
+    }
+
+    // Takes a picture and leaves it in RAM as tiles, ready to be copied to
+    // VRAM.
+    fn capture(&mut self) {
+        if !photo_fits(&self.ram) {
+            return;
+        }
+        let shades = process(&self.camera.capture(), &self.registers);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let tile = y / 8 * (CAMERA_WIDTH / 8) + x / 8;
+                let index = PHOTO_ADDR + tile * 16 + y % 8 * 2;
+                let bit = 0x80 >> (x % 8);
+                let shade = shades[y * CAMERA_WIDTH + x];
+                self.ram[index] = self.ram[index] & !bit | if shade & 0x01 != 0 { bit } else { 0 };
+                self.ram[index + 1] =
+                    self.ram[index + 1] & !bit | if shade & 0x02 != 0 { bit } else { 0 };
+            }
+        }
+    }
+}
+
+// What the sensor and the cartridge make of an image, as shades from 0
+// (white) to 3 (black). The exposure time in 0xA002-0xA003 scales the
+// brightness, with 0x1000 leaving it as it is. With N and VH all set in
+// 0xA001 each pixel is pushed away from its neighbours, which is the edge
+// enhancement the camera ROM uses. The other filtering modes and the gain
+// aren't modelled. Then 0xA004 bit 7 inverts the image, and each pixel is
+// compared with its thresholds in the dithering matrix, which is how the
+// ROM sets contrast and brightness too.
+fn process(image: &[u8], registers: &[u8; REGISTER_COUNT]) -> Vec<u8> {
+    let exposure = ((registers[0x02] as u32) << 8 | registers[0x03] as u32) as f32 / 0x1000 as f32;
+    let edges = registers[0x01] & 0xE0 == 0xE0;
+    let edge_ratio = EDGE_RATIOS[((registers[0x04] >> 4) & 0x07) as usize];
+    let invert = registers[0x04] & 0x80 != 0;
+    let pixel = |x: usize, y: usize| image[y * CAMERA_WIDTH + x] as f32 * exposure;
+
+    let mut shades = Vec::with_capacity(CAMERA_WIDTH * CAMERA_HEIGHT);
+    for y in 0..CAMERA_HEIGHT {
+        for x in 0..CAMERA_WIDTH {
+            let mut val = pixel(x, y);
+            if edges {
+                // The edges of the image count as their own neighbours.
+                let neighbours = pixel(x.saturating_sub(1), y)
+                    + pixel((x + 1).min(CAMERA_WIDTH - 1), y)
+                    + pixel(x, y.saturating_sub(1))
+                    + pixel(x, (y + 1).min(CAMERA_HEIGHT - 1));
+                val += edge_ratio * (4.0 * val - neighbours);
+            }
+            let mut val = val.round().clamp(0.0, 255.0) as u8;
+            if invert {
+                val = 255 - val;
+            }
+            let matrix = MATRIX_REGISTER + (y % 4 * 4 + x % 4) * 3;
+            let thresholds = &registers[matrix..matrix + 3];
+            shades.push(
+                match thresholds.iter().position(|&threshold| val < threshold) {
+                    Some(index) => 3 - index as u8,
+                    None => 0,
+                },
+            );
+        }
+    }
+    shades
+}
+
+impl super::MBC for PocketCamera {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    fn set_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x1F,
+            _ => (),
+        }
+    }
+
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if self.registers_mapped() {
+            // Only the first register can be read back, and the registers
+            // repeat every 0x80 bytes.
+            return if addr & 0x7F == 0 {
+                self.registers[0] & 0x07
+            } else {
+                0x00
+            };
+        }
+        match super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if self.registers_mapped() {
+            let register = (addr & 0x7F) as usize;
+            if register == 0 {
+                self.registers[0] = val & 0x07;
+                // Bit 0 starts a capture, and reads 1 until it's done.
+                if val & 0x01 != 0 {
+                    self.capture();
+                    self.registers[0] &= !0x01;
+                }
+            } else if register < REGISTER_COUNT {
+                self.registers[register] = val;
+            }
+            return;
+        }
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(index) = super::ram_index(&self.ram, self.ram_bank as usize, addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    struct Flat(u8);
+
+    impl ImageSource for Flat {
+        fn capture(&self) -> Vec<u8> {
+            vec![self.0; CAMERA_WIDTH * CAMERA_HEIGHT]
+        }
+    }
+
+    // Exposure 0x1000 and the same thresholds everywhere.
+    fn registers(thresholds: [u8; 3]) -> [u8; REGISTER_COUNT] {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[0x02] = 0x10;
+        for i in 0..16 {
+            registers[MATRIX_REGISTER + i * 3..][..3].copy_from_slice(&thresholds);
+        }
+        registers
+    }
+
+    #[test]
+    fn thresholds_pick_the_shade() {
+        let registers = registers([0x40, 0x80, 0xC0]);
+        for &(val, shade) in &[(0x00, 3), (0x40, 2), (0xBF, 1), (0xC0, 0), (0xFF, 0)] {
+            assert_eq!(process(&Flat(val).capture(), &registers)[0], shade);
+        }
+        // Halving the exposure darkens it.
+        let mut darker = registers;
+        darker[0x02] = 0x08;
+        assert_eq!(process(&Flat(0xC0).capture(), &darker)[0], 2);
+        let mut inverted = registers;
+        inverted[0x04] = 0x80;
+        assert_eq!(process(&Flat(0xFF).capture(), &inverted)[0], 3);
+    }
+
+    #[test]
+    fn dithering_matrix_varies_by_position() {
+        let mut registers = registers([0x40, 0x80, 0xC0]);
+        // Make the second pixel of each row of four darker.
+        registers[MATRIX_REGISTER + 3..][..3].copy_from_slice(&[0x90, 0xA0, 0xB0]);
+        let shades = process(&Flat(0x88).capture(), &registers);
+        assert_eq!(shades[..6], [1, 3, 1, 1, 1, 3]);
+        assert_eq!(shades[CAMERA_WIDTH + 1], 1);
+    }
+
+    #[test]
+    fn edges_are_enhanced() {
+        let mut image = vec![0x60; CAMERA_WIDTH * CAMERA_HEIGHT];
+        for row in image.chunks_mut(CAMERA_WIDTH) {
+            row[CAMERA_WIDTH / 2..]
+                .iter_mut()
+                .for_each(|val| *val = 0xA0);
+        }
+        let mut registers = registers([0x40, 0x80, 0xC0]);
+        let plain = process(&image, &registers);
+        registers[0x01] = 0xE0;
+        // Ratio 1
+        registers[0x04] = 0x20;
+        let enhanced = process(&image, &registers);
+        let edge = CAMERA_WIDTH * 8 + CAMERA_WIDTH / 2;
+        assert_eq!(plain[edge - 2..edge + 2], [2, 2, 1, 1]);
+        assert_eq!(enhanced[edge - 2..edge + 2], [2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn captures_end_up_in_ram_as_tiles() {
+        // 128KB of RAM
+        let cartridge = test_cartridge(0xFC, 0x05, 0x04).with_camera(Rc::new(Flat(0xFF)));
+        let mut mbc = PocketCamera::new(cartridge);
+        mbc.set_rom(0x4000, 0x10);
+        for (i, val) in registers([0x40, 0x80, 0xC0]).iter().enumerate().skip(1) {
+            mbc.set_ram(0xA000 + i as u16, *val);
+        }
+        // Nothing in RAM is 0 before the capture.
+        mbc.ram[..0x2000].iter_mut().for_each(|val| *val = 0x55);
+
+        mbc.set_rom(0x4000, 0x10);
+        mbc.set_ram(0xA080, 0x03);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x02);
+        mbc.set_rom(0x4000, 0x00);
+        assert!(mbc.ram[PHOTO_ADDR..PHOTO_ADDR + PHOTO_SIZE]
+            .iter()
+            .all(|&val| val == 0x00));
+        // and nothing either side of it is touched.
+        assert_eq!(mbc.fetch_ram(0xA0FF), 0x55);
+        assert_eq!(mbc.fetch_ram(0xAF00), 0x55);
+    }
+
+    #[test]
+    fn captures_need_room_in_ram() {
+        // A photo only just fits in 0xF00 bytes, which is when the warning
+        // stops.
+        assert!(photo_fits(&[0; 0xF00]));
+        assert!(!photo_fits(&[0; 0xEFF]));
+
+        // 8KB of RAM is plenty.
+        let cartridge = test_cartridge(0xFC, 0x05, 0x02).with_camera(Rc::new(Flat(0xFF)));
+        let mut mbc = PocketCamera::new(cartridge);
+        mbc.set_rom(0x4000, 0x10);
+        for (i, val) in registers([0x40, 0x80, 0xC0]).iter().enumerate().skip(1) {
+            mbc.set_ram(0xA000 + i as u16, *val);
+        }
+        mbc.ram.iter_mut().for_each(|val| *val = 0x55);
+        mbc.set_ram(0xA000, 0x01);
+        assert_eq!(mbc.fetch_ram(0xA000), 0x00);
+        assert!(mbc.ram[PHOTO_ADDR..PHOTO_ADDR + PHOTO_SIZE]
+            .iter()
+            .all(|&val| val == 0x00));
+        assert_eq!(mbc.ram[PHOTO_ADDR - 1], 0x55);
+        assert_eq!(mbc.ram[PHOTO_ADDR + PHOTO_SIZE], 0x55);
+
+        // 2KB is too small, so nothing is written at all.
+        let cartridge = test_cartridge(0xFC, 0x05, 0x01).with_camera(Rc::new(Flat(0xFF)));
+        let mut mbc = PocketCamera::new(cartridge);
+        assert!(!photo_fits(&mbc.ram));
+        mbc.ram.iter_mut().for_each(|val| *val = 0x55);
+        mbc.set_rom(0x4000, 0x10);
+        mbc.set_ram(0xA000, 0x01);
+        assert!(mbc.ram.iter().all(|&val| val == 0x55));
+    }
+}
@@ -0,0 +1,74 @@
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// 32KB of ROM wired straight to the bus, and maybe up to 8KB of RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+}
+
+impl RomOnly {
+    pub fn new(cartridge: Cartridge) -> RomOnly {
+        let mut ram = super::cartridge_ram(&cartridge);
+        ram.truncate(super::RAM_BANK_SIZE);
+        RomOnly {
+            ram,
+            battery: cartridge.header().cartridge_type.battery,
+            rom: cartridge.into_rom(),
+        }
+    }
+}
+
+impl super::MBC for RomOnly {
+    // Without banking, a short ROM leaves part of the address space empty.
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        self.rom
+            .get(addr as usize)
+            .cloned()
+            .ok_or(EmulatorError::OutOfBoundsAccess {
+                addr,
+                rom_size: self.rom.len(),
+            })
+    }
+
+    // There's nothing to write to.
+    fn set_rom(&mut self, _addr: u16, _val: u8) {}
+
+    // Without any RAM, reads float high.
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        self.ram
+            .get((addr - 0xA000) as usize)
+            .cloned()
+            .unwrap_or(0xFF)
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if let Some(byte) = self.ram.get_mut((addr - 0xA000) as usize) {
+            *byte = val;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        super::battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    #[test]
+    fn writes_do_not_change_the_rom() {
+        let mut mbc = RomOnly::new(test_cartridge(0x00, 0x00, 0x00));
+        mbc.set_rom(0x2000, 0x01);
+        mbc.set_rom(0x4000, 0x42);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x01);
+        assert_eq!(mbc.fetch_ram(0xA000), 0xFF);
+    }
+}
@@ -0,0 +1,159 @@
+extern crate log;
+
+use self::log::warn;
+use std::sync::Once;
+
+use cartridge::Cartridge;
+use error::EmulatorError;
+
+// Bandai's mapper for Tamagotchi 3. Everything goes through two registers:
+// 0xA001 picks one of the chip's registers, and 0xA000 writes or reads a
+// nibble of it. Besides the ROM bank there are 32 bytes of battery backed
+// memory, and a real time clock that isn't supported yet, so Tamagotchi 3's
+// time and date won't persist or advance.
+pub struct TAMA5 {
+    rom: Vec<u8>,
+    memory: [u8; 0x20],
+    rom_bank: u8,
+    // Selected through 0xA001.
+    register: u8,
+    // The byte to write and where, and what the command is, as written to
+    // registers 4-6.
+    data: u8,
+    addr_command: u8,
+    // What the last read came back with, through registers 0xC and 0xD.
+    result: u8,
+}
+
+// Games poke the clock constantly, so only warn about it once.
+static CLOCK_WARNING: Once = Once::new();
+
+impl TAMA5 {
+    pub fn new(cartridge: Cartridge) -> TAMA5 {
+        TAMA5 {
+            rom: cartridge.into_rom(),
+            memory: [0; 0x20],
+            rom_bank: 0,
+            register: 0,
+            data: 0,
+            addr_command: 0,
+            result: 0,
+        }
+    }
+
+    // Writing register 7 sets the low bits of the address and runs the
+    // command in register 6.
+    fn run_command(&mut self, addr_low: u8) {
+        let addr = ((self.addr_command & 0x01) << 4 | addr_low) as usize;
+        match self.addr_command >> 1 {
+            0 => self.memory[addr] = self.data,
+            1 => self.result = self.memory[addr],
+            _ => CLOCK_WARNING
+                .call_once(|| warn!("TAMA5 clock commands aren't supported yet, ignoring them")),
+        }
+    }
+}
+
+impl super::MBC for TAMA5 {
+    fn fetch_rom(&self, addr: u16) -> Result<u8, EmulatorError> {
+        let bank = if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        };
+        Ok(super::rom_byte(&self.rom, bank, addr))
+    }
+
+    // There are no registers in the ROM area.
+    fn set_rom(&mut self, _addr: u16, _val: u8) {}
+
+    // Only the low nibble means anything.
+    fn fetch_ram(&self, addr: u16) -> u8 {
+        if addr & 0x01 != 0 {
+            return 0xFF;
+        }
+        match self.register {
+            // Ready, which it always is.
+            0xA => 0xF1,
+            0xC => 0xF0 | self.result & 0x0F,
+            0xD => 0xF0 | self.result >> 4,
+            _ => 0xFF,
+        }
+    }
+
+    fn set_ram(&mut self, addr: u16, val: u8) {
+        if addr & 0x01 != 0 {
+            self.register = val & 0x0F;
+            return;
+        }
+        let val = val & 0x0F;
+        match self.register {
+            0x0 => self.rom_bank = self.rom_bank & 0x10 | val,
+            0x1 => self.rom_bank = self.rom_bank & 0x0F | (val & 0x01) << 4,
+            0x4 => self.data = self.data & 0xF0 | val,
+            0x5 => self.data = self.data & 0x0F | val << 4,
+            0x6 => self.addr_command = val,
+            0x7 => self.run_command(val),
+            _ => (),
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.memory.to_vec())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram(&mut self.memory, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_cartridge, MBC};
+    use super::*;
+
+    fn write(mbc: &mut TAMA5, register: u8, val: u8) {
+        mbc.set_ram(0xA001, register);
+        mbc.set_ram(0xA000, val);
+    }
+
+    fn read(mbc: &mut TAMA5, register: u8) -> u8 {
+        mbc.set_ram(0xA001, register);
+        mbc.fetch_ram(0xA000)
+    }
+
+    #[test]
+    fn rom_bank_takes_two_registers() {
+        // 512KB
+        let mut mbc = TAMA5::new(test_cartridge(0xFD, 0x04, 0x00));
+        assert_eq!(read(&mut mbc, 0x0A), 0xF1);
+        write(&mut mbc, 0x0, 0x05);
+        write(&mut mbc, 0x1, 0x01);
+        assert_eq!(mbc.fetch_rom(0x4000).unwrap(), 0x15);
+    }
+
+    #[test]
+    fn memory_is_written_and_read_a_nibble_at_a_time() {
+        let mut mbc = TAMA5::new(test_cartridge(0xFD, 0x04, 0x00));
+        write(&mut mbc, 0x4, 0x2);
+        write(&mut mbc, 0x5, 0x4);
+        // Write to 0x13
+        write(&mut mbc, 0x6, 0x01);
+        write(&mut mbc, 0x7, 0x3);
+        // and read it back.
+        write(&mut mbc, 0x6, 0x03);
+        write(&mut mbc, 0x7, 0x3);
+        assert_eq!(read(&mut mbc, 0xC), 0xF2);
+        assert_eq!(read(&mut mbc, 0xD), 0xF4);
+        assert_eq!(mbc.save_data().unwrap()[0x13], 0x42);
+
+        // Anything else is the clock, which is ignored and leaves the last
+        // result and the memory alone.
+        let memory = mbc.save_data();
+        write(&mut mbc, 0x6, 0x04);
+        write(&mut mbc, 0x7, 0x0);
+        assert_eq!(read(&mut mbc, 0xC), 0xF2);
+        assert_eq!(read(&mut mbc, 0xD), 0xF4);
+        assert_eq!(mbc.save_data(), memory);
+    }
+}
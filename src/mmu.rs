@@ -1,21 +1,304 @@
-use std::io::Error;
+use apu::APU;
+use cartridge::Cartridge;
+use error::EmulatorError;
+use interrupt::Interrupt;
+use joypad::{Button, Joypad};
+use mbc::MBC;
+use model::Model;
+use ppu::PPU;
+use serial::Serial;
+use timer::Timer;
 
+// An OAM DMA transfer in progress, copying 160 bytes one per M-cycle.
+struct Dma {
+    source: u16,
+    index: u8,
+    // The first M-cycle after the write to 0xFF46 is spent setting up.
+    starting: bool,
+}
+
+// The bus between the CPU and everything else, which it owns.
 pub struct MMU {
-    mbc: ::mbc::MBC,
+    mbc: Box<dyn MBC>,
+    ppu: PPU,
+    wram: [u8; 0x2000],
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    // IF (0xFF0F) and IE (0xFFFF)
+    interrupt_flag: u8,
+    ie: u8,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    apu: APU,
+    dma: Option<Dma>,
+    dma_register: u8,
+    // KEY1 (0xFF4D) only exists on the CGB.
+    cgb: bool,
+    double_speed: bool,
+    speed_switch_armed: bool,
+    // Told whenever the cartridge's rumble motor starts or stops.
+    rumble_handler: Option<Box<dyn FnMut(bool)>>,
 }
 
 impl MMU {
-    pub fn new() -> Result<MMU, Error> {
-        Ok(MMU {
-            mbc: ::mbc::MBC::new()?,
-        })
+    // Starts with the I/O registers as the model's boot ROM leaves them.
+    pub fn new(model: Model, cartridge: Cartridge) -> MMU {
+        let mut mmu = MMU {
+            mbc: ::mbc::from_cartridge(cartridge),
+            ppu: PPU::new(),
+            wram: [0; 0x2000],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            interrupt_flag: 0,
+            ie: 0,
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(model.is_cgb()),
+            apu: APU::new(),
+            dma: None,
+            dma_register: 0,
+            cgb: model.is_cgb(),
+            double_speed: false,
+            speed_switch_armed: false,
+            rumble_handler: None,
+        };
+        for (addr, val) in model.io_registers() {
+            match addr {
+                // Writing DIV would reset it.
+                0xFF04 => mmu.timer.set_div(val),
+                // and writing DMA would start a transfer.
+                0xFF46 => mmu.dma_register = val,
+                _ => mmu.set_io(addr, val),
+            }
+        }
+        mmu
+    }
+
+    // Reads as the CPU sees it. Only the cartridge can fail, when the
+    // address is past the end of the ROM.
+    pub fn fetch(&self, addr: u16) -> Result<u8, EmulatorError> {
+        // While OAM DMA is copying, the CPU can only get at the top page.
+        if self.dma_active() && addr < 0xFF00 {
+            return Ok(0xFF);
+        }
+        let val = match addr {
+            0x0000..=0x7FFF => return self.mbc.fetch_rom(addr),
+            0x8000..=0x9FFF => self.ppu.fetch_vram(addr),
+            0xA000..=0xBFFF => self.mbc.fetch_ram(addr),
+            0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize],
+            // Echo RAM mirrors work RAM
+            0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize],
+            0xFE00..=0xFE9F => self.ppu.fetch_oam(addr),
+            // Unusable memory
+            0xFEA0..=0xFEFF => 0xFF,
+            0xFF00..=0xFF7F => self.fetch_io(addr),
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
+            0xFFFF => self.ie,
+        };
+        Ok(val)
+    }
+
+    pub fn set_mem_addr(&mut self, addr: u16, val: u8) -> Result<(), EmulatorError> {
+        if self.dma_active() && addr < 0xFF00 {
+            return Ok(());
+        }
+        match addr {
+            0x0000..=0x7FFF => self.set_mbc_register(addr, val),
+            0x8000..=0x9FFF => self.ppu.set_vram(addr, val),
+            0xA000..=0xBFFF => self.mbc.set_ram(addr, val),
+            0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize] = val,
+            0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize] = val,
+            0xFE00..=0xFE9F => self.ppu.set_oam(addr, val),
+            0xFEA0..=0xFEFF => (),
+            0xFF00..=0xFF7F => self.set_io(addr, val),
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = val,
+            0xFFFF => self.ie = val,
+        }
+        Ok(())
+    }
+
+    fn set_mbc_register(&mut self, addr: u16, val: u8) {
+        let rumble = self.mbc.rumble();
+        self.mbc.set_rom(addr, val);
+        if self.mbc.rumble() != rumble {
+            if let Some(ref mut handler) = self.rumble_handler {
+                handler(!rumble);
+            }
+        }
+    }
+
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mbc.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc.load_save_data(data);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    pub fn on_rumble(&mut self, handler: Box<dyn FnMut(bool)>) {
+        self.rumble_handler = Some(handler);
+    }
+
+    // I/O registers that have hardware behind them. The rest are plain
+    // memory for now.
+    fn fetch_io(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF00 => self.joypad.fetch(),
+            0xFF01..=0xFF02 => self.serial.fetch(addr),
+            0xFF04..=0xFF07 => self.timer.fetch(addr),
+            // Only the low 5 bits of IF exist, the rest read back as 1.
+            0xFF0F => self.interrupt_flag | 0xE0,
+            0xFF10..=0xFF3F => self.apu.fetch(addr),
+            0xFF46 => self.dma_register,
+            0xFF40..=0xFF4B => self.ppu.fetch(addr),
+            0xFF4D => self.key1(),
+            _ => self.io[(addr - 0xFF00) as usize],
+        }
+    }
+
+    fn set_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF00 => self.joypad.set(val),
+            0xFF01..=0xFF02 => self.serial.set(addr, val),
+            0xFF04..=0xFF07 => self.timer.set_mem_addr(addr, val),
+            0xFF0F => self.interrupt_flag = val & 0x1F,
+            0xFF10..=0xFF3F => self.apu.set(addr, val),
+            0xFF46 => {
+                self.dma_register = val;
+                self.dma = Some(Dma {
+                    source: (val as u16) << 8,
+                    index: 0,
+                    starting: true,
+                });
+            }
+            0xFF40..=0xFF4B => self.ppu.set(addr, val),
+            0xFF4D => self.speed_switch_armed = self.cgb && val & 0x01 != 0,
+            _ => self.io[(addr - 0xFF00) as usize] = val,
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.bit();
+    }
+
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
+    }
+
+    // Interrupts that are both requested and enabled.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.ie & 0x1F
+    }
+
+    // Advances everything else by one M-cycle. The CPU calls this for each
+    // memory access, so the rest of the system sees the accesses at the
+    // right time. In double speed mode the timer runs off the CPU clock, but
+    // the PPU doesn't, so it only gets half as far.
+    pub fn tick(&mut self) {
+        if self.timer.tick(4) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        if self.serial.tick(4) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        let dots = if self.double_speed { 2 } else { 4 };
+        self.interrupt_flag |= self.ppu.tick(dots);
+        self.apu.tick(dots as u32);
+        self.tick_dma();
+    }
+
+    fn tick_dma(&mut self) {
+        let (source, index) = match self.dma {
+            Some(ref mut dma) if dma.starting => {
+                dma.starting = false;
+                return;
+            }
+            Some(ref dma) => (dma.source, dma.index),
+            None => return,
+        };
+        let val = self.dma_fetch(source.wrapping_add(index as u16));
+        self.ppu.dma_set_oam(index, val);
+        self.dma = if index as usize == 0x9F {
+            None
+        } else {
+            Some(Dma {
+                source,
+                index: index + 1,
+                starting: false,
+            })
+        };
+    }
+
+    // Reads for OAM DMA, which can't see I/O or HRAM. Sources from 0xE000 up
+    // read from work RAM instead.
+    fn dma_fetch(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.mbc.fetch_rom(addr).unwrap_or(0xFF),
+            0x8000..=0x9FFF => self.ppu.dma_fetch_vram(addr),
+            0xA000..=0xBFFF => self.mbc.fetch_ram(addr),
+            _ => self.wram[((addr - 0xC000) & 0x1FFF) as usize],
+        }
+    }
+
+    fn dma_active(&self) -> bool {
+        self.dma.as_ref().is_some_and(|dma| !dma.starting)
+    }
+
+    pub fn frame(&self) -> &[u8] {
+        self.ppu.frame()
+    }
+
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    pub fn reset_div(&mut self) {
+        self.timer.reset_div();
+    }
+
+    pub fn press_button(&mut self, button: Button) {
+        if self.joypad.press(button) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    pub fn release_button(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    pub fn joypad_line_low(&self) -> bool {
+        self.joypad.any_line_low()
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
     }
 
-    pub fn fetch(&self, addr: u16) -> u8 {
-        self.mbc.fetch_rom(addr)
+    // Performs the speed switch prepared through KEY1, if there is one.
+    // Called by STOP.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        true
     }
 
-    pub fn set_mem_addr(&mut self, addr: u16, val: u8) {
-        self.mbc.set_mem_addr(addr, val);
+    // Bit 7 is the current speed and bit 0 the prepared switch.
+    fn key1(&self) -> u8 {
+        if !self.cgb {
+            return 0xFF;
+        }
+        0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
     }
 }
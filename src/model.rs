@@ -0,0 +1,195 @@
+use register::{Flags, Registers};
+
+// Game Boy hardware revisions. Each boot ROM leaves the registers in a
+// slightly different state, which games use to tell the hardware apart.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    // Original Game Boy with the early boot ROM
+    DMG0,
+    #[default]
+    DMG,
+    // Game Boy Pocket
+    MGB,
+    // Super Game Boy
+    SGB,
+    SGB2,
+    // Game Boy Color
+    CGB,
+    // Game Boy Advance running Game Boy Color software
+    AGB,
+}
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::SGB | Model::SGB2)
+    }
+
+    // CPU registers once the boot ROM hands over to the cartridge at 0x100.
+    // The DMG and MGB boot ROMs leave H and C set unless the header checksum
+    // at 0x14D is zero.
+    pub fn registers(self, header_checksum: u8) -> Registers {
+        let checksum_flags = header_checksum != 0;
+        let (a, f, [b, c, d, e, h, l]) = match self {
+            Model::DMG0 => (0x01, 0x00, [0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03]),
+            Model::DMG | Model::MGB => {
+                let a = if self == Model::MGB { 0xFF } else { 0x01 };
+                let f = 0x80 | if checksum_flags { 0x30 } else { 0x00 };
+                (a, f, [0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D])
+            }
+            Model::SGB => (0x01, 0x00, [0x00, 0x14, 0x00, 0x00, 0xC0, 0x60]),
+            Model::SGB2 => (0xFF, 0x00, [0x00, 0x14, 0x00, 0x00, 0xC0, 0x60]),
+            Model::CGB => (0x11, 0x80, [0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D]),
+            // Same as the CGB, except the boot ROM does an extra INC B.
+            Model::AGB => (0x11, 0x00, [0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D]),
+        };
+
+        Registers {
+            a,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xFFFE,
+            pc: 0x0100,
+            f: Flags::from_bits(f),
+        }
+    }
+
+    // Hardware registers that the boot ROM leaves in a known state, as
+    // (address, value) pairs. Anything not listed reads back as zero.
+    pub fn io_registers(self) -> Vec<(u16, u8)> {
+        let cgb = self.is_cgb();
+        let mut io = vec![
+            // P1
+            (0xFF00, 0xCF),
+            // SB, SC
+            (0xFF01, 0x00),
+            (0xFF02, if cgb { 0x7F } else { 0x7E }),
+            // DIV. Only known on the DMG and MGB, so use the same value on
+            // the others.
+            (0xFF04, if self == Model::DMG0 { 0x18 } else { 0xAB }),
+            // TIMA, TMA, TAC
+            (0xFF05, 0x00),
+            (0xFF06, 0x00),
+            (0xFF07, 0xF8),
+            // IF, with VBlank left requested by the boot ROM
+            (0xFF0F, 0xE1),
+            // Sound
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            (0xFF14, 0xBF),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF18, 0xFF),
+            (0xFF19, 0xBF),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (0xFF1E, 0xBF),
+            (0xFF20, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0xBF),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+            (0xFF26, if self.is_sgb() { 0xF0 } else { 0xF1 }),
+            // LCDC, STAT, SCY, SCX
+            (0xFF40, 0x91),
+            (0xFF41, if self == Model::DMG0 { 0x81 } else { 0x85 }),
+            (0xFF42, 0x00),
+            (0xFF43, 0x00),
+            // LY
+            (0xFF44, if self == Model::DMG0 { 0x91 } else { 0x00 }),
+            // LYC, DMA, BGP, OBP0, OBP1, WY, WX
+            (0xFF45, 0x00),
+            (0xFF46, if cgb { 0x00 } else { 0xFF }),
+            (0xFF47, 0xFC),
+            (0xFF48, 0x00),
+            (0xFF49, 0x00),
+            (0xFF4A, 0x00),
+            (0xFF4B, 0x00),
+        ];
+
+        // CGB only registers read back as 0xFF on the older models.
+        io.extend(if cgb {
+            vec![
+                // KEY1, VBK
+                (0xFF4D, 0x7E),
+                (0xFF4F, 0xFE),
+                // HDMA1-5
+                (0xFF51, 0xFF),
+                (0xFF52, 0xFF),
+                (0xFF53, 0xFF),
+                (0xFF54, 0xFF),
+                (0xFF55, 0xFF),
+                // RP, SVBK
+                (0xFF56, 0x3E),
+                (0xFF70, 0xF8),
+            ]
+        } else {
+            vec![
+                (0xFF4D, 0xFF),
+                (0xFF4F, 0xFF),
+                (0xFF51, 0xFF),
+                (0xFF52, 0xFF),
+                (0xFF53, 0xFF),
+                (0xFF54, 0xFF),
+                (0xFF55, 0xFF),
+                (0xFF56, 0xFF),
+                (0xFF68, 0xFF),
+                (0xFF69, 0xFF),
+                (0xFF6A, 0xFF),
+                (0xFF6B, 0xFF),
+                (0xFF70, 0xFF),
+            ]
+        });
+        io
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_models_start_at_the_entry_point() {
+        for model in &[
+            Model::DMG0,
+            Model::DMG,
+            Model::MGB,
+            Model::SGB,
+            Model::SGB2,
+            Model::CGB,
+            Model::AGB,
+        ] {
+            let registers = model.registers(0);
+            assert_eq!(registers.pc, 0x0100);
+            assert_eq!(registers.sp, 0xFFFE);
+        }
+    }
+
+    #[test]
+    fn a_and_b_identify_the_hardware() {
+        assert_eq!(Model::DMG.registers(0).a, 0x01);
+        assert_eq!(Model::MGB.registers(0).a, 0xFF);
+        assert_eq!(Model::CGB.registers(0).a, 0x11);
+        assert_eq!(Model::CGB.registers(0).b, 0x00);
+        assert_eq!(Model::AGB.registers(0).b, 0x01);
+    }
+
+    #[test]
+    fn dmg_flags_depend_on_header_checksum() {
+        assert_eq!(Model::DMG.registers(0).f.bits(), 0x80);
+        assert_eq!(Model::DMG.registers(0x42).f.bits(), 0xB0);
+        assert_eq!(Model::SGB.registers(0x42).f.bits(), 0x00);
+    }
+}
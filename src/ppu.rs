@@ -0,0 +1,479 @@
+use interrupt::Interrupt;
+
+// Each line takes 456 dots, split into OAM scan, drawing and HBlank. Lines
+// 144-153 are VBlank.
+const LINE_DOTS: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+const VISIBLE_LINES: u8 = 144;
+const LINES: u8 = 154;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = VISIBLE_LINES as usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing,
+}
+
+impl Mode {
+    // The value in the low two bits of STAT.
+    fn bits(self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3,
+        }
+    }
+}
+
+// The LCD controller's timing, registers and the memory it owns. The CPU
+// can't get at VRAM while the PPU is drawing, or at OAM while it's scanning
+// or drawing.
+pub struct PPU {
+    vram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+    lcdc: u8,
+    // Only the interrupt enable bits 3-6 of STAT are stored, the rest come
+    // from the current state.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    // Position within the current line
+    dot: u16,
+    mode: Mode,
+    // The STAT interrupt is requested when any of its sources turns on, so
+    // remember whether one already was.
+    stat_line: bool,
+    // The window's own line counter, which only moves on lines it's drawn
+    // on.
+    window_line: u8,
+    // Shades from 0 (white) to 3 (black), a line at a time.
+    frame: Vec<u8>,
+}
+
+// Picks the shade for a colour number out of a palette register.
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+impl PPU {
+    pub fn new() -> PPU {
+        PPU {
+            vram: [0; 0x2000],
+            oam: [0; 0xA0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            dot: 0,
+            mode: Mode::HBlank,
+            stat_line: false,
+            window_line: 0,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    // The last frame drawn, SCREEN_WIDTH shades per line.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    pub fn fetch_vram(&self, addr: u16) -> u8 {
+        if self.mode == Mode::Drawing {
+            return 0xFF;
+        }
+        self.vram[(addr - 0x8000) as usize]
+    }
+
+    pub fn set_vram(&mut self, addr: u16, val: u8) {
+        if self.mode != Mode::Drawing {
+            self.vram[(addr - 0x8000) as usize] = val;
+        }
+    }
+
+    pub fn fetch_oam(&self, addr: u16) -> u8 {
+        if self.oam_locked() {
+            return 0xFF;
+        }
+        self.oam[(addr - 0xFE00) as usize]
+    }
+
+    pub fn set_oam(&mut self, addr: u16, val: u8) {
+        if !self.oam_locked() {
+            self.oam[(addr - 0xFE00) as usize] = val;
+        }
+    }
+
+    // VRAM as OAM DMA sees it, which doesn't care what the PPU is doing.
+    pub fn dma_fetch_vram(&self, addr: u16) -> u8 {
+        self.vram[(addr - 0x8000) as usize]
+    }
+
+    pub fn dma_set_oam(&mut self, index: u8, val: u8) {
+        self.oam[index as usize] = val;
+    }
+
+    pub fn fetch(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => 0x80 | self.stat | ((self.ly == self.lyc) as u8) << 2 | self.mode.bits(),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => unreachable!("Not a PPU register: {:04x}", addr),
+        }
+    }
+
+    pub fn set(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = val;
+                if was_enabled && !self.lcd_enabled() {
+                    // Turning the LCD off resets it to the top of the screen.
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.mode = Mode::HBlank;
+                    self.window_line = 0;
+                    // A blank screen is all white.
+                    self.frame.iter_mut().for_each(|pixel| *pixel = 0);
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            }
+            0xFF41 => self.stat = val & 0x78,
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+            // LY is read only.
+            0xFF44 => (),
+            0xFF45 => self.lyc = val,
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+            _ => unreachable!("Not a PPU register: {:04x}", addr),
+        }
+    }
+
+    // Runs for some number of dots, returning the IF bits of any interrupts
+    // requested along the way.
+    pub fn tick(&mut self, dots: u8) -> u8 {
+        let mut interrupts = 0;
+        if !self.lcd_enabled() {
+            return interrupts;
+        }
+
+        for _ in 0..dots {
+            self.dot += 1;
+            if self.dot == LINE_DOTS {
+                self.dot = 0;
+                self.ly = (self.ly + 1) % LINES;
+                if self.ly == VISIBLE_LINES {
+                    interrupts |= Interrupt::VBlank.bit();
+                } else if self.ly == 0 {
+                    self.window_line = 0;
+                }
+            }
+
+            let mode = if self.ly >= VISIBLE_LINES {
+                Mode::VBlank
+            } else if self.dot < OAM_SCAN_DOTS {
+                Mode::OamScan
+            } else if self.dot < OAM_SCAN_DOTS + DRAWING_DOTS {
+                Mode::Drawing
+            } else {
+                Mode::HBlank
+            };
+            // The whole line is drawn at once, as drawing ends.
+            if self.mode == Mode::Drawing && mode == Mode::HBlank {
+                self.render_line();
+            }
+            self.mode = mode;
+
+            let stat_line = self.stat_line();
+            if stat_line && !self.stat_line {
+                interrupts |= Interrupt::LcdStat.bit();
+            }
+            self.stat_line = stat_line;
+        }
+        interrupts
+    }
+
+    fn render_line(&mut self) {
+        // Colour numbers of the background and window, which sprites with
+        // bit 7 set hide behind.
+        let mut bg = [0; SCREEN_WIDTH];
+        // LCDC bit 0 turns both the background and the window off.
+        if self.lcdc & 0x01 != 0 {
+            let bg_map = if self.lcdc & 0x08 != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            let y = self.scy.wrapping_add(self.ly);
+            for (x, color) in bg.iter_mut().enumerate() {
+                *color = self.map_pixel(bg_map, self.scx.wrapping_add(x as u8), y);
+            }
+
+            // WX is the window's left edge plus 7.
+            if self.lcdc & 0x20 != 0 && self.ly >= self.wy && self.wx < 167 {
+                let window_map = if self.lcdc & 0x40 != 0 {
+                    0x9C00
+                } else {
+                    0x9800
+                };
+                let start = self.wx.saturating_sub(7) as usize;
+                for (x, color) in bg.iter_mut().enumerate().skip(start) {
+                    let window_x = (x + 7 - self.wx as usize) as u8;
+                    *color = self.map_pixel(window_map, window_x, self.window_line);
+                }
+                self.window_line += 1;
+            }
+        }
+
+        let mut pixels = [0; SCREEN_WIDTH];
+        for (pixel, color) in pixels.iter_mut().zip(bg.iter()) {
+            *pixel = shade(self.bgp, *color);
+        }
+        if self.lcdc & 0x02 != 0 {
+            self.render_sprites(&bg, &mut pixels);
+        }
+        let start = self.ly as usize * SCREEN_WIDTH;
+        self.frame[start..start + SCREEN_WIDTH].copy_from_slice(&pixels);
+    }
+
+    // Up to 10 sprites on a line, picked in OAM order. Where they overlap the
+    // one furthest left wins, then the one earliest in OAM, so they're drawn
+    // the other way round.
+    fn render_sprites(&self, bg: &[u8; SCREEN_WIDTH], pixels: &mut [u8; SCREEN_WIDTH]) {
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        let ly = self.ly as i16;
+        let mut sprites: Vec<&[u8]> = self
+            .oam
+            .chunks(4)
+            .filter(|sprite| {
+                let y = sprite[0] as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(10)
+            .collect();
+        // A stable sort keeps OAM order between sprites at the same X.
+        sprites.sort_by_key(|sprite| sprite[1]);
+
+        for sprite in sprites.iter().rev() {
+            let y = sprite[0] as i16 - 16;
+            let x = sprite[1] as i16 - 8;
+            let flags = sprite[3];
+            // 8x16 sprites ignore the low bit of the tile number.
+            let tile = if height == 16 {
+                sprite[2] & 0xFE
+            } else {
+                sprite[2]
+            };
+            let mut row = (ly - y) as u8;
+            if flags & 0x40 != 0 {
+                row = height as u8 - 1 - row;
+            }
+            let palette = if flags & 0x10 != 0 {
+                self.obp1
+            } else {
+                self.obp0
+            };
+
+            for col in 0..8 {
+                let screen_x = x + col;
+                if screen_x < 0 || screen_x >= SCREEN_WIDTH as i16 {
+                    continue;
+                }
+                let col = if flags & 0x20 != 0 { 7 - col } else { col };
+                let color = self.tile_pixel(0x8000 + tile as u16 * 16, row, col as u8);
+                // Colour 0 is transparent.
+                let hidden = flags & 0x80 != 0 && bg[screen_x as usize] != 0;
+                if color != 0 && !hidden {
+                    pixels[screen_x as usize] = shade(palette, color);
+                }
+            }
+        }
+    }
+
+    // Colour number at a position in one of the 32x32 tile maps.
+    fn map_pixel(&self, map: u16, x: u8, y: u8) -> u8 {
+        let index = (map - 0x8000) as usize + (y as usize / 8) * 32 + x as usize / 8;
+        let tile = self.vram[index];
+        // LCDC bit 4 picks between unsigned tile numbers from 0x8000 and
+        // signed ones from 0x9000.
+        let tile_addr = if self.lcdc & 0x10 != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            0x9000u16.wrapping_add((tile as i8 as i16 * 16) as u16)
+        };
+        self.tile_pixel(tile_addr, y % 8, x % 8)
+    }
+
+    // Tiles are 2 bytes a row, with the low bit of each pixel's colour
+    // number in the first and the high bit in the second.
+    fn tile_pixel(&self, tile_addr: u16, row: u8, col: u8) -> u8 {
+        let addr = (tile_addr - 0x8000) as usize + row as usize * 2;
+        let bit = 7 - col;
+        let low = (self.vram[addr] >> bit) & 1;
+        let high = (self.vram[addr + 1] >> bit) & 1;
+        (high << 1) | low
+    }
+
+    fn oam_locked(&self) -> bool {
+        matches!(self.mode, Mode::OamScan | Mode::Drawing)
+    }
+
+    // The OR of all of the enabled STAT interrupt sources.
+    fn stat_line(&self) -> bool {
+        let mode_source = match self.mode {
+            Mode::HBlank => self.stat & 0x08 != 0,
+            Mode::VBlank => self.stat & 0x10 != 0,
+            Mode::OamScan => self.stat & 0x20 != 0,
+            Mode::Drawing => false,
+        };
+        mode_source || (self.stat & 0x40 != 0 && self.ly == self.lyc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_ppu() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.set(0xFF40, 0x91);
+        ppu
+    }
+
+    #[test]
+    fn modes_follow_line_timing() {
+        let mut ppu = enabled_ppu();
+        assert_eq!(ppu.mode, Mode::OamScan);
+        ppu.tick(80);
+        assert_eq!(ppu.mode, Mode::Drawing);
+        assert_eq!(ppu.fetch_vram(0x8000), 0xFF);
+        ppu.tick(172);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        ppu.tick(204);
+        assert_eq!(ppu.fetch(0xFF44), 1);
+        assert_eq!(ppu.mode, Mode::OamScan);
+    }
+
+    #[test]
+    fn vblank_interrupt_at_line_144() {
+        let mut ppu = enabled_ppu();
+        let mut interrupts = 0;
+        for _ in 0..143 {
+            interrupts |= ppu.tick(228);
+            interrupts |= ppu.tick(228);
+        }
+        assert_eq!(interrupts, 0);
+        interrupts = ppu.tick(228);
+        interrupts |= ppu.tick(228);
+        assert_eq!(interrupts, Interrupt::VBlank.bit());
+        assert_eq!(ppu.mode, Mode::VBlank);
+        assert_eq!(ppu.fetch(0xFF41) & 0x03, 1);
+    }
+
+    #[test]
+    fn lyc_stat_interrupt() {
+        let mut ppu = enabled_ppu();
+        ppu.set(0xFF45, 2);
+        ppu.set(0xFF41, 0x40);
+        assert_eq!(ppu.tick(228), 0);
+        assert_eq!(ppu.tick(228), 0);
+        assert_eq!(ppu.tick(228), 0);
+        assert_eq!(ppu.tick(228), Interrupt::LcdStat.bit());
+        assert_eq!(ppu.fetch(0xFF41) & 0x04, 0x04);
+    }
+
+    // Runs the PPU to the end of the first line's drawing.
+    fn draw_first_line(ppu: &mut PPU) {
+        ppu.tick(OAM_SCAN_DOTS as u8);
+        ppu.tick(DRAWING_DOTS as u8);
+    }
+
+    #[test]
+    fn background_scrolls_and_uses_bgp() {
+        let mut ppu = PPU::new();
+        // Tile 1 has colour 1 on the left half and 3 on the right half.
+        ppu.set_vram(0x8010, 0xFF);
+        ppu.set_vram(0x8011, 0x0F);
+        ppu.set_vram(0x9800, 0x01);
+        ppu.set(0xFF47, 0xE4);
+        ppu.set(0xFF43, 2);
+        ppu.set(0xFF40, 0x91);
+        draw_first_line(&mut ppu);
+        assert_eq!(&ppu.frame()[..8], &[1, 1, 3, 3, 3, 3, 0, 0]);
+    }
+
+    #[test]
+    fn sprites_draw_over_the_background() {
+        let mut ppu = PPU::new();
+        ppu.set_vram(0x8010, 0x80);
+        // Sprite 0 uses tile 1, flipped, at the top left corner.
+        for (i, val) in [16, 8, 1, 0x20].iter().enumerate() {
+            ppu.set_oam(0xFE00 + i as u16, *val);
+        }
+        ppu.set(0xFF48, 0xE4);
+        ppu.set(0xFF40, 0x93);
+        draw_first_line(&mut ppu);
+        assert_eq!(&ppu.frame()[..8], &[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Behind the background, it only shows over colour 0.
+        let mut ppu = PPU::new();
+        ppu.set_vram(0x8010, 0x81);
+        ppu.set_vram(0x8000, 0x7F);
+        for (i, val) in [16, 8, 1, 0x80].iter().enumerate() {
+            ppu.set_oam(0xFE00 + i as u16, *val);
+        }
+        ppu.set(0xFF47, 0xE4);
+        ppu.set(0xFF48, 0xFF);
+        ppu.set(0xFF40, 0x93);
+        draw_first_line(&mut ppu);
+        assert_eq!(&ppu.frame()[..8], &[3, 1, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn turning_lcd_off_resets_ly() {
+        let mut ppu = enabled_ppu();
+        ppu.tick(228);
+        ppu.tick(228);
+        ppu.set(0xFF40, 0x11);
+        assert_eq!(ppu.fetch(0xFF44), 0);
+        assert_eq!(ppu.tick(228), 0);
+        ppu.set_vram(0x8000, 0x42);
+        assert_eq!(ppu.fetch_vram(0x8000), 0x42);
+    }
+}
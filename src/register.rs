@@ -1,6 +1,76 @@
 use std::convert::TryInto;
 use std::fmt;
 
+// The F register. Only the top nibble exists, so the low nibble always reads
+// back as zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    bits: u8,
+}
+
+impl Flags {
+    const Z: u8 = 0b10000000;
+    const N: u8 = 0b01000000;
+    const H: u8 = 0b00100000;
+    const C: u8 = 0b00010000;
+
+    pub fn from_bits(bits: u8) -> Flags {
+        Flags { bits: bits & 0xF0 }
+    }
+
+    pub fn bits(self) -> u8 {
+        self.bits
+    }
+
+    // Sets all four flags at once, which is what most instructions do.
+    pub fn set(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.set_z(z);
+        self.set_n(n);
+        self.set_h(h);
+        self.set_c(c);
+    }
+
+    pub fn z(self) -> bool {
+        self.bits & Flags::Z != 0
+    }
+
+    pub fn n(self) -> bool {
+        self.bits & Flags::N != 0
+    }
+
+    pub fn h(self) -> bool {
+        self.bits & Flags::H != 0
+    }
+
+    pub fn c(self) -> bool {
+        self.bits & Flags::C != 0
+    }
+
+    pub fn set_z(&mut self, val: bool) {
+        self.set_bit(Flags::Z, val);
+    }
+
+    pub fn set_n(&mut self, val: bool) {
+        self.set_bit(Flags::N, val);
+    }
+
+    pub fn set_h(&mut self, val: bool) {
+        self.set_bit(Flags::H, val);
+    }
+
+    pub fn set_c(&mut self, val: bool) {
+        self.set_bit(Flags::C, val);
+    }
+
+    fn set_bit(&mut self, mask: u8, val: bool) {
+        if val {
+            self.bits |= mask;
+        } else {
+            self.bits &= !mask;
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Registers {
     pub a: u8,
@@ -12,7 +82,7 @@ pub struct Registers {
     pub l: u8,
     pub sp: u16,
     pub pc: u16,
-    pub f: u8,
+    pub f: Flags,
 }
 
 // Add display for easier debugging.
@@ -37,18 +107,20 @@ impl fmt::Display for Registers {
 }
 
 impl Registers {
+    // Everything cleared, as at power on. See Model::registers for the
+    // state after the boot ROM has run.
     pub fn new() -> Registers {
         Registers {
-            a: 5,
-            b: 9,
+            a: 0,
+            b: 0,
             c: 0,
             d: 0,
             e: 0,
             h: 0,
-            l: 1,
+            l: 0,
             pc: 0,
-            sp: 0xFFFE,
-            f: 0,
+            sp: 0,
+            f: Flags::default(),
         }
     }
 
@@ -64,6 +136,10 @@ impl Registers {
         ((self.h as u16) << 8) + self.l as u16
     }
 
+    pub fn af(&self) -> u16 {
+        ((self.a as u16) << 8) | self.f.bits() as u16
+    }
+
     // Include set functions to make things easier for combined registers.
     pub fn set_bc(&mut self, num: u16) {
         let b_num: u8 = (num >> 8).try_into().unwrap();
@@ -86,19 +162,58 @@ impl Registers {
         self.l = l_num;
     }
 
+    // Used by POP AF, which can't set the low nibble of F.
+    pub fn set_af(&mut self, num: u16) {
+        self.a = (num >> 8) as u8;
+        self.f = Flags::from_bits(num as u8);
+    }
+
     pub fn z(&self) -> bool {
-        self.f >> 7 == 1
+        self.f.z()
     }
 
     pub fn n(&self) -> bool {
-        self.f >> 6 == 1
+        self.f.n()
     }
 
     pub fn h(&self) -> bool {
-        self.f >> 5 == 1
+        self.f.h()
     }
 
     pub fn c(&self) -> bool {
-        self.f >> 4 == 1
+        self.f.c()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_low_nibble_is_always_zero() {
+        let flags = Flags::from_bits(0xFF);
+        assert_eq!(flags.bits(), 0xF0);
+    }
+
+    #[test]
+    fn flags_are_independent() {
+        let mut flags = Flags::default();
+        flags.set_z(true);
+        flags.set_c(true);
+        assert!(flags.z());
+        assert!(!flags.n());
+        assert!(!flags.h());
+        assert!(flags.c());
+        flags.set_z(false);
+        assert_eq!(flags.bits(), 0b00010000);
+    }
+
+    #[test]
+    fn af_round_trips_through_set_af() {
+        let mut registers = Registers::new();
+        registers.set_af(0x12FF);
+        assert_eq!(registers.a, 0x12);
+        assert_eq!(registers.af(), 0x12F0);
+        assert!(registers.z() && registers.n() && registers.h() && registers.c());
     }
 }
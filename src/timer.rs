@@ -0,0 +1,151 @@
+// DIV, TIMA, TMA and TAC at 0xFF04-0xFF07.
+//
+// DIV is the top byte of a 16 bit counter that goes up every cycle. TIMA
+// goes up whenever the counter bit picked by TAC falls from 1 to 0, which is
+// why resetting DIV can also bump TIMA.
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+        }
+    }
+
+    // Only for setting up the post-boot state, writing DIV normally resets it.
+    pub fn set_div(&mut self, div: u8) {
+        self.counter = (div as u16) << 8;
+    }
+
+    pub fn reset_div(&mut self) {
+        let before = self.input();
+        self.counter = 0;
+        if before && !self.input() {
+            self.increment_tima();
+        }
+    }
+
+    pub fn fetch(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            // Only the low 3 bits of TAC exist.
+            0xFF07 => self.tac | 0xF8,
+            _ => unreachable!("Not a timer register: {:04x}", addr),
+        }
+    }
+
+    pub fn set_mem_addr(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF04 => self.reset_div(),
+            0xFF05 => self.tima = val,
+            0xFF06 => self.tma = val,
+            0xFF07 => {
+                // Turning the timer off or changing the frequency can also
+                // cause a falling edge.
+                let before = self.input();
+                self.tac = val & 0x07;
+                if before && !self.input() {
+                    self.increment_tima();
+                }
+            }
+            _ => unreachable!("Not a timer register: {:04x}", addr),
+        }
+    }
+
+    // Runs the timer for some number of cycles, returning whether TIMA
+    // overflowed and the timer interrupt should be requested.
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        let mut overflowed = false;
+        for _ in 0..cycles {
+            let before = self.input();
+            self.counter = self.counter.wrapping_add(1);
+            if before && !self.input() {
+                overflowed |= self.increment_tima();
+            }
+        }
+        overflowed
+    }
+
+    // The counter bit TIMA watches, ANDed with the enable bit.
+    fn input(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && (self.counter >> bit) & 1 == 1
+    }
+
+    // On overflow TIMA is reloaded from TMA.
+    fn increment_tima(&mut self) -> bool {
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = if overflowed { self.tma } else { tima };
+        overflowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_counts_every_256_cycles() {
+        let mut timer = Timer::new();
+        for _ in 0..4 {
+            timer.tick(64);
+        }
+        assert_eq!(timer.fetch(0xFF04), 1);
+        timer.set_mem_addr(0xFF04, 0x42);
+        assert_eq!(timer.fetch(0xFF04), 0);
+    }
+
+    #[test]
+    fn tima_counts_at_selected_frequency() {
+        let mut timer = Timer::new();
+        // Enabled, every 16 cycles
+        timer.set_mem_addr(0xFF07, 0x05);
+        timer.tick(64);
+        assert_eq!(timer.fetch(0xFF05), 4);
+    }
+
+    #[test]
+    fn tima_overflow_reloads_from_tma() {
+        let mut timer = Timer::new();
+        timer.set_mem_addr(0xFF05, 0xFF);
+        timer.set_mem_addr(0xFF06, 0x80);
+        timer.set_mem_addr(0xFF07, 0x05);
+        assert!(!timer.tick(15));
+        assert!(timer.tick(1));
+        assert_eq!(timer.fetch(0xFF05), 0x80);
+    }
+
+    #[test]
+    fn disabled_timer_does_not_count() {
+        let mut timer = Timer::new();
+        timer.set_mem_addr(0xFF07, 0x01);
+        timer.tick(255);
+        assert_eq!(timer.fetch(0xFF05), 0);
+        assert_eq!(timer.fetch(0xFF07), 0xF9);
+    }
+
+    #[test]
+    fn resetting_div_can_increment_tima() {
+        let mut timer = Timer::new();
+        timer.set_mem_addr(0xFF07, 0x05);
+        // Bit 3 of the counter is now set.
+        timer.tick(8);
+        timer.reset_div();
+        assert_eq!(timer.fetch(0xFF05), 1);
+    }
+}